@@ -0,0 +1,504 @@
+use std::{collections::{HashMap, HashSet}, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Local, Utc};
+use tokio::sync::watch::{Receiver, Sender};
+use tracing::{debug, info, warn};
+use twitch_gql_rs::{TwitchClient, error::TwitchError, structs::{CampaignDetails, DropCampaigns, InventorySelf, TimeBasedDropsCampaignDetails}};
+
+use crate::{clock::sleep_or_resume, config::{Config, UnlinkedPolicy}, drops::{DropGraph, is_wanted, matches_reward}, r#static::{DROP_CASH, GAME_SLUGS, OWNED_BENEFITS}};
+
+const AT_RISK_RATIO: f64 = 0.8;
+const OWNED_REWARDS_NOTE: &str = "Owned rewards are detected from campaigns still in progress and from drops this miner claimed, not from drops claimed elsewhere in campaigns that already ended";
+
+/// A game identified by its Twitch game id and slug. Both stay the same between runs,
+/// unlike the position of the game in the campaign list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Game {
+    pub id: String,
+    pub slug: String,
+    pub name: String
+}
+
+impl Game {
+    pub fn matches (&self, query: &str) -> bool {
+        self.id == query || self.slug.eq_ignore_ascii_case(query) || self.name.eq_ignore_ascii_case(query)
+    }
+}
+
+impl std::fmt::Display for Game {
+    fn fmt (&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.slug, self.id)
+    }
+}
+
+pub struct GameCampaigns {
+    pub game: Game,
+    /// Campaigns that are running right now
+    pub campaigns: Vec<DropCampaigns>,
+    /// Campaigns that have not started yet
+    pub upcoming: Vec<DropCampaigns>
+}
+
+pub fn parse_time (time: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time).ok().map(|time| time.with_timezone(&Utc))
+}
+
+pub fn local_time (time: DateTime<Utc>) -> String {
+    time.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string()
+}
+
+pub fn format_time (time: &str) -> String {
+    parse_time(time).map(local_time).unwrap_or_else(|| time.to_string())
+}
+
+/// Time left until `time`, zero if it has already passed.
+pub fn until (time: DateTime<Utc>) -> Duration {
+    (time - Utc::now()).to_std().unwrap_or_default()
+}
+
+pub fn is_upcoming (campaign: &DropCampaigns) -> bool {
+    parse_time(&campaign.startAt).is_some_and(|start| start > Utc::now())
+}
+
+pub fn is_ended (campaign: &DropCampaigns) -> bool {
+    campaign.status == "EXPIRED" || parse_time(&campaign.endAt).is_some_and(|end| end <= Utc::now())
+}
+
+/// Earliest moment one of `campaigns` starts or ends, whichever comes first.
+pub fn next_transition<'a> (campaigns: impl IntoIterator<Item = &'a DropCampaigns>) -> Option<DateTime<Utc>> {
+    let now = Utc::now();
+    campaigns.into_iter()
+        .flat_map(|campaign| [parse_time(&campaign.startAt), parse_time(&campaign.endAt)])
+        .flatten()
+        .filter(|time| *time > now)
+        .min()
+}
+
+/// Sleep length until the next refresh: `refresh`, or less if a campaign starts or ends sooner.
+pub fn refresh_delay (refresh: Duration, next: Option<DateTime<Utc>>) -> Duration {
+    match next {
+        Some(next) => refresh.min(until(next) + Duration::from_secs(5)),
+        None => refresh
+    }
+}
+
+async fn game_slug (client: &TwitchClient, game_id: &str, name: &str) -> String {
+    let mut slugs = GAME_SLUGS.lock().await;
+    if let Some(slug) = slugs.get(game_id) {
+        return slug.clone();
+    }
+    match client.get_slug(name).await {
+        Ok(slug) => {
+            slugs.insert(game_id.to_string(), slug.clone());
+            slug
+        },
+        Err(e) => {
+            tracing::error!("Failed to resolve slug for {name}: {e}");
+            game_id.to_string()
+        }
+    }
+}
+
+/// Groups campaigns by game into running and upcoming ones, sorted by game name. Ended campaigns are skipped.
+pub async fn group_campaigns (client: &TwitchClient, campaigns: Vec<DropCampaigns>) -> Vec<GameCampaigns> {
+    let mut grouped: Vec<GameCampaigns> = Vec::new();
+    for campaign in campaigns {
+        if is_ended(&campaign) {
+            continue;
+        }
+        let pos = match grouped.iter().position(|group| group.game.id == campaign.game.id) {
+            Some(pos) => pos,
+            None => {
+                let slug = game_slug(client, &campaign.game.id, &campaign.game.displayName).await;
+                let game = Game { id: campaign.game.id.clone(), slug, name: campaign.game.displayName.clone() };
+                grouped.push(GameCampaigns { game, campaigns: Vec::new(), upcoming: Vec::new() });
+                grouped.len() - 1
+            }
+        };
+        if is_upcoming(&campaign) {
+            grouped[pos].upcoming.push(campaign);
+        } else {
+            grouped[pos].campaigns.push(campaign);
+        }
+    }
+    grouped.sort_by_key(|group| group.game.name.to_lowercase());
+    grouped
+}
+
+pub async fn fetch_campaigns (client: &TwitchClient) -> Result<Vec<GameCampaigns>, TwitchError> {
+    let campaigns = client.get_campaign().await?.dropCampaigns;
+    Ok(group_campaigns(client, campaigns).await)
+}
+
+pub fn find_game<'a> (grouped: &'a [GameCampaigns], query: &str) -> Option<&'a GameCampaigns> {
+    grouped.iter().find(|group| group.game.matches(query))
+}
+
+/// Keeps only the campaigns listed by id or name; an empty list keeps everything.
+pub fn filter_campaigns (campaigns: &[DropCampaigns], wanted: &[String]) -> Vec<DropCampaigns> {
+    if wanted.is_empty() {
+        return campaigns.to_vec();
+    }
+    campaigns.iter().filter(|campaign| wanted.iter().any(|w| campaign.id == *w || campaign.name.eq_ignore_ascii_case(w))).cloned().collect()
+}
+
+/// Time-based drops of a campaign that are still worth watching for: not claimed, reward not owned
+/// already, not gated behind subscriptions, not blocked by preconditions that can never be met and
+/// able to finish before the drop or its campaign ends.
+/// With a reward wishlist only the wanted drops and the drops they depend on are kept.
+pub async fn remaining_drops (campaign_details: &CampaignDetails, inventory: &InventoryState, rewards: &[String]) -> Vec<TimeBasedDropsCampaignDetails> {
+    let done = inventory.done_drops(&campaign_details.timeBasedDrops).await;
+    let graph = DropGraph::new(&campaign_details.timeBasedDrops);
+    let watched = inventory.minutes_watched();
+    let start = parse_time(&campaign_details.startAt).map_or(Utc::now(), |start| start.max(Utc::now()));
+    let campaign_end = parse_time(&campaign_details.endAt);
+    // A drop that cannot finish in time is skipped, and so are the drops only it depends on
+    let wanted_ids: Vec<&String> = campaign_details.timeBasedDrops.iter().filter(|time_based| is_wanted(time_based, rewards)).filter(|time_based| {
+        if time_based.requiredSubs > 0 || done.contains(&time_based.id) {
+            return true;
+        }
+        let end = [campaign_end, parse_time(&time_based.endAt)].into_iter().flatten().min();
+        match (end, graph.eta_minutes(&time_based.id, &done, &watched)) {
+            (Some(end), Some(minutes)) if start + chrono::Duration::minutes(minutes as i64) > end => {
+                warn!("Skipping {} ({}): needs {} more min but ends {}", time_based.name, time_based.id, minutes, local_time(end));
+                false
+            },
+            _ => true
+        }
+    }).map(|time_based| &time_based.id).collect();
+    let wanted = graph.with_preconditions(wanted_ids);
+    campaign_details.timeBasedDrops.iter().filter(|time_based| {
+        if !wanted.contains(&time_based.id) {
+            return false;
+        }
+        if time_based.requiredSubs > 0 || done.contains(&time_based.id) {
+            if inventory.owns(time_based) && !inventory.progress.get(&time_based.id).is_some_and(|drop_self| drop_self.isClaimed) {
+                info!("Reward of {} ({}) is already owned, treating the drop as done", time_based.name, time_based.id);
+            }
+            return false;
+        }
+        if !graph.is_reachable(&time_based.id, &done) {
+            warn!("{} ({}) is blocked by preconditions that cannot be earned: {:?}", time_based.name, time_based.id, graph.preconditions(&time_based.id));
+            return false;
+        }
+        true
+    }).cloned().collect()
+}
+
+/// Warns about every wishlist entry that matches no drop of `campaigns`, e.g. a typo or a reward of another game.
+pub async fn warn_unmatched_rewards (client: &TwitchClient, campaigns: &[DropCampaigns], rewards: &[String]) {
+    let mut unmatched: Vec<&String> = rewards.iter().collect();
+    for campaign in campaigns {
+        if unmatched.is_empty() {
+            return;
+        }
+        match client.get_campaign_details(&campaign.id).await {
+            Ok(campaign_details) => unmatched.retain(|reward| !campaign_details.timeBasedDrops.iter().any(|time_based| matches_reward(time_based, reward))),
+            Err(e) => {
+                tracing::error!("Failed to load campaign {} to check the reward wishlist: {e}", campaign.id);
+                return;
+            }
+        }
+    }
+    for reward in unmatched {
+        warn!("Reward '{reward}' matches no drop id, drop name or reward name of the selected campaigns");
+    }
+}
+
+/// Rewards of this campaign are only granted once the Twitch account is linked to the publisher.
+pub fn needs_account_link (campaign_details: &CampaignDetails) -> bool {
+    !campaign_details.self_drop.isAccountConnected && !campaign_details.accountLinkURL.is_empty()
+}
+
+/// Next time a campaign of a watchlisted game starts or ends.
+pub fn watchlist_transition (grouped: &[GameCampaigns], watchlist: &[String]) -> Option<DateTime<Utc>> {
+    let groups: Vec<&GameCampaigns> = watchlist.iter().filter_map(|query| find_game(grouped, query)).collect();
+    next_transition(groups.iter().flat_map(|group| group.campaigns.iter().chain(&group.upcoming)))
+}
+
+/// Drop progress and owned rewards, as reported by the inventory.
+///
+/// Owned rewards come from the claimed drops of `dropCampaignsInProgress` and from the rewards of
+/// the drops this miner claimed. The claimed rewards of campaigns that already ended (`gameEventDrops`)
+/// are not exposed by twitch-gql-rs, so a reward earned elsewhere in an ended campaign is not detected as owned.
+#[derive(Debug, Default)]
+pub struct InventoryState {
+    pub progress: HashMap<String, InventorySelf>,
+    pub owned_benefits: HashSet<String>
+}
+
+impl InventoryState {
+    pub async fn load (client: &TwitchClient) -> Result<Self, TwitchError> {
+        let inv = client.get_inventory().await?;
+        let mut state = InventoryState { owned_benefits: OWNED_BENEFITS.lock().await.clone(), ..Default::default() };
+        for in_progress in inv.inventory.dropCampaignsInProgress.unwrap_or_default() {
+            for time_based in in_progress.timeBasedDrops {
+                if time_based.self_drop.isClaimed {
+                    state.owned_benefits.extend(time_based.benefitEdges.iter().map(|edge| edge.benefit.id.clone()));
+                }
+                state.progress.insert(time_based.id, time_based.self_drop);
+            }
+        }
+        Ok(state)
+    }
+
+    /// Every reward of the drop is already in the inventory, e.g. from another campaign in progress with the same reward.
+    pub fn owns (&self, time_based: &TimeBasedDropsCampaignDetails) -> bool {
+        !time_based.benefitEdges.is_empty() && time_based.benefitEdges.iter().all(|edge| self.owned_benefits.contains(&edge.benefit.id))
+    }
+
+    /// Drops that count as earned: claimed by the miner, marked claimed in the inventory,
+    /// or one of `drops` whose reward is already owned.
+    pub async fn done_drops (&self, drops: &[TimeBasedDropsCampaignDetails]) -> HashSet<String> {
+        let mut done = DROP_CASH.lock().await.clone();
+        done.extend(self.progress.iter().filter(|(_, drop_self)| drop_self.isClaimed).map(|(id, _)| id.clone()));
+        for time_based in drops {
+            if self.owns(time_based) {
+                done.insert(time_based.id.clone());
+            }
+        }
+        done
+    }
+
+    pub fn minutes_watched (&self) -> HashMap<String, u64> {
+        self.progress.iter().map(|(id, drop_self)| (id.clone(), drop_self.currentMinutesWatched)).collect()
+    }
+}
+
+/// Orders campaigns earliest deadline first, skipping campaigns with nothing left to earn,
+/// including campaigns whose remaining drops can no longer be finished before they end.
+/// Campaigns whose account is not linked are handled according to `config.unlinked_campaigns`.
+pub async fn plan_campaigns (client: &TwitchClient, campaigns: &[DropCampaigns], config: &Config) -> Vec<DropCampaigns> {
+    let inventory = InventoryState::load(client).await.unwrap_or_else(|e| {
+        tracing::error!("Failed to load inventory: {e}");
+        InventoryState::default()
+    });
+    let watched = inventory.minutes_watched();
+    let mut planned: Vec<(Option<DateTime<Utc>>, u64, DropCampaigns)> = Vec::new();
+    for campaign in campaigns {
+        let campaign_details = match client.get_campaign_details(&campaign.id).await {
+            Ok(campaign_details) => campaign_details,
+            Err(e) => {
+                tracing::error!("Failed to load campaign {}: {e}", campaign.id);
+                planned.push((None, 0, campaign.clone()));
+                continue;
+            }
+        };
+        if needs_account_link(&campaign_details) {
+            match config.unlinked_campaigns {
+                UnlinkedPolicy::Skip => {
+                    warn!("Skipping {} ({}): account not linked, link it at {}", campaign.name, campaign.id, campaign_details.accountLinkURL);
+                    continue;
+                },
+                UnlinkedPolicy::Warn => warn!("{} ({}) only grants rewards to linked accounts, link it at {}", campaign.name, campaign.id, campaign_details.accountLinkURL),
+                UnlinkedPolicy::Mine => {}
+            }
+        }
+        let drops = remaining_drops(&campaign_details, &inventory, &config.rewards).await;
+        if drops.is_empty() {
+            debug!("Nothing left to earn in {} ({})", campaign.name, campaign.id);
+            continue;
+        }
+        // Independent drops progress together and chained ones one after another, so the longest chain decides
+        let graph = DropGraph::new(&campaign_details.timeBasedDrops);
+        let done = inventory.done_drops(&campaign_details.timeBasedDrops).await;
+        let minutes = drops.iter().filter_map(|time_based| graph.eta_minutes(&time_based.id, &done, &watched)).max().unwrap_or(0);
+        let deadline = parse_time(&campaign.endAt);
+        planned.push((deadline, minutes, campaign.clone()));
+    }
+    planned.sort_by(|a, b| match (a.0, b.0) {
+        (Some(a_end), Some(b_end)) => a_end.cmp(&b_end).then(b.1.cmp(&a.1)),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => b.1.cmp(&a.1),
+    });
+    planned.into_iter().map(|(_, _, campaign)| campaign).collect()
+}
+
+/// First game of the watchlist that still has drops to earn, with its progressable campaigns.
+/// Games in `skip` are passed over.
+pub async fn next_watchlist_game (client: &TwitchClient, grouped: &[GameCampaigns], config: &Config, skip: &HashSet<String>) -> Option<(Game, Vec<DropCampaigns>)> {
+    for query in &config.watchlist {
+        let Some(group) = find_game(grouped, query).filter(|group| !skip.contains(&group.game.id)) else {
+            continue;
+        };
+        let campaigns = plan_campaigns(client, &group.campaigns, config).await;
+        if !campaigns.is_empty() {
+            return Some((group.game.clone(), campaigns));
+        }
+    }
+    None
+}
+
+/// Publishes a new campaign list to the watchers, logging which campaigns were added, removed or changed.
+pub fn apply_campaigns (tx_campaigns: &Sender<Vec<DropCampaigns>>, campaigns: Vec<DropCampaigns>) -> bool {
+    tx_campaigns.send_if_modified(|current| {
+        if *current == campaigns {
+            return false;
+        }
+        for campaign in &campaigns {
+            match current.iter().find(|old| old.id == campaign.id) {
+                None => info!("Campaign added: {} ({})", campaign.name, campaign.id),
+                Some(old) if old != campaign => info!("Campaign updated: {} ({}), ends {}", campaign.name, campaign.id, campaign.endAt),
+                Some(_) => {}
+            }
+        }
+        for old in current.iter().filter(|old| !campaigns.iter().any(|campaign| campaign.id == old.id)) {
+            info!("Campaign removed: {} ({})", old.name, old.id);
+        }
+        *current = campaigns;
+        true
+    })
+}
+
+/// Re-fetches the campaigns of the mined games every `refresh` and publishes the changes.
+/// Wakes up early when a campaign is due to start or end, so upcoming campaigns begin on time,
+/// and after a resume, when campaigns may have opened or ended in the meantime.
+pub async fn campaign_refresher (client: Arc<TwitchClient>, game_ids: Vec<String>, config: Config, tx_campaigns: Sender<Vec<DropCampaigns>>, tx_upcoming: Sender<Vec<DropCampaigns>>, refresh: Duration, mut rx_resume: Receiver<u64>) {
+    tokio::spawn(async move {
+        loop {
+            let next = next_transition(tx_campaigns.borrow().iter().chain(tx_upcoming.borrow().iter()));
+            sleep_or_resume(refresh_delay(refresh, next), &mut rx_resume).await;
+            match fetch_campaigns(&client).await {
+                Ok(grouped) => {
+                    let mut campaigns = Vec::new();
+                    let mut upcoming = Vec::new();
+                    for group in grouped.iter().filter(|group| game_ids.contains(&group.game.id)) {
+                        campaigns.extend(filter_campaigns(&group.campaigns, &config.campaigns));
+                        upcoming.extend(filter_campaigns(&group.upcoming, &config.campaigns));
+                    }
+                    tx_upcoming.send_replace(upcoming);
+                    apply_campaigns(&tx_campaigns, plan_campaigns(&client, &campaigns, &config).await);
+                },
+                Err(e) => tracing::error!("Failed to refresh campaigns: {e}"),
+            }
+        }
+    });
+}
+
+/// Whether a drop can still be earned by watching continuously until it ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Feasibility {
+    Claimed,
+    Owned,
+    WillFinish,
+    AtRisk,
+    Impossible
+}
+
+impl Feasibility {
+    pub fn new (remaining_minutes: u64, time_left: Duration) -> Self {
+        let left_minutes = time_left.as_secs() / 60;
+        if remaining_minutes == 0 {
+            Feasibility::WillFinish
+        } else if remaining_minutes > left_minutes {
+            Feasibility::Impossible
+        } else if remaining_minutes as f64 > left_minutes as f64 * AT_RISK_RATIO {
+            Feasibility::AtRisk
+        } else {
+            Feasibility::WillFinish
+        }
+    }
+}
+
+impl std::fmt::Display for Feasibility {
+    fn fmt (&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Feasibility::Claimed => "claimed",
+            Feasibility::Owned => "reward already owned",
+            Feasibility::WillFinish => "will finish",
+            Feasibility::AtRisk => "at risk",
+            Feasibility::Impossible => "impossible"
+        };
+        write!(f, "{text}")
+    }
+}
+
+pub fn format_duration (duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+    if days > 0 {
+        format!("{days}d {hours}h")
+    } else if hours > 0 {
+        format!("{hours}h {minutes}m")
+    } else {
+        format!("{minutes}m")
+    }
+}
+
+/// Per campaign and per time-based drop: required minutes, current progress and time left,
+/// flagged by whether the drop can be finished with continuous watching.
+pub async fn feasibility_report (client: &TwitchClient, campaigns: &[DropCampaigns], rewards: &[String]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let inventory = InventoryState::load(client).await?;
+    let watched = inventory.minutes_watched();
+    let now = Utc::now();
+    let mut lines = Vec::new();
+    for campaign in campaigns {
+        let campaign_details = client.get_campaign_details(&campaign.id).await?;
+        let campaign_end = parse_time(&campaign.endAt);
+        // Upcoming campaigns can only be watched once they open
+        let start = parse_time(&campaign.startAt).map_or(now, |start| start.max(now));
+        let graph = DropGraph::new(&campaign_details.timeBasedDrops);
+        let done = inventory.done_drops(&campaign_details.timeBasedDrops).await;
+        let wanted = graph.with_preconditions(campaign_details.timeBasedDrops.iter().filter(|time_based| is_wanted(time_based, rewards)).map(|time_based| &time_based.id));
+        let mut drop_lines = Vec::new();
+        let mut worst: Option<Feasibility> = None;
+        for time_based in &campaign_details.timeBasedDrops {
+            let drop_self = inventory.progress.get(&time_based.id);
+            let current = drop_self.map_or(0, |drop_self| drop_self.currentMinutesWatched);
+            let end = [campaign_end, parse_time(&time_based.endAt)].into_iter().flatten().min();
+            let time_left = end.map_or(Duration::MAX, |end| (end - start).to_std().unwrap_or_default());
+            if time_based.requiredSubs > 0 {
+                drop_lines.push(format!("    {}: requires {} subscription(s), skipped", time_based.name, time_based.requiredSubs));
+                continue;
+            }
+            if !wanted.contains(&time_based.id) {
+                drop_lines.push(format!("    {}: not on the reward wishlist", time_based.name));
+                continue;
+            }
+            let feasibility = if drop_self.is_some_and(|drop_self| drop_self.isClaimed) || DROP_CASH.lock().await.contains(&time_based.id) {
+                Feasibility::Claimed
+            } else if done.contains(&time_based.id) {
+                Feasibility::Owned
+            } else {
+                graph.eta_minutes(&time_based.id, &done, &watched).map_or(Feasibility::Impossible, |eta| Feasibility::new(eta, time_left))
+            };
+            if !matches!(feasibility, Feasibility::Claimed | Feasibility::Owned) {
+                worst = worst.max(Some(feasibility));
+            }
+            let pending: Vec<&str> = graph.preconditions(&time_based.id).iter().filter(|pre| !done.contains(*pre)).map(|pre| graph.name(pre)).collect();
+            let after = if pending.is_empty() { String::new() } else { format!(", after {}", pending.join(", ")) };
+            drop_lines.push(format!("    {}: {}/{} min, {} left{} - {}", time_based.name, current.min(time_based.requiredMinutesWatched), time_based.requiredMinutesWatched, format_duration(time_left), after, feasibility));
+        }
+        let status = worst.map_or("nothing left to earn".to_string(), |worst| worst.to_string());
+        let ends = campaign_end.map_or("unknown".to_string(), |end| format!("{} ({} left)", local_time(end), format_duration(until(end))));
+        lines.push(format!("{} ({}), ends {} - {}", campaign.name, campaign.id, ends, status));
+        if needs_account_link(&campaign_details) {
+            lines.push(format!("    account not linked, rewards need linking at {}", campaign_details.accountLinkURL));
+        }
+        lines.extend(drop_lines);
+    }
+    if !campaigns.is_empty() {
+        lines.push(OWNED_REWARDS_NOTE.to_string());
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Feasibility;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    #[test]
+    fn feasibility_thresholds () {
+        assert_eq!(Feasibility::new(0, Duration::ZERO), Feasibility::WillFinish);
+        assert_eq!(Feasibility::new(48, HOUR), Feasibility::WillFinish);
+        assert_eq!(Feasibility::new(49, HOUR), Feasibility::AtRisk);
+        assert_eq!(Feasibility::new(60, HOUR), Feasibility::AtRisk);
+        assert_eq!(Feasibility::new(61, HOUR), Feasibility::Impossible);
+        assert_eq!(Feasibility::new(1, Duration::MAX), Feasibility::WillFinish);
+    }
+}
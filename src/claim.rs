@@ -0,0 +1,295 @@
+use std::{collections::HashSet, path::{Path, PathBuf}, sync::Arc, time::Duration};
+
+use tokio::{fs, sync::{mpsc::{self, UnboundedSender}, watch}, time::sleep};
+use tracing::{debug, info};
+use twitch_gql_rs::{TwitchClient, error::{ClaimDropError, TwitchError}, structs::{InventorySelf, InventoryTimeBasedDrops}};
+
+use crate::{clock::sleep_or_resume, r#static::{CLAIMING, DROP_CASH, OWNED_BENEFITS}};
+
+/// Reward ids of claimed drops, stored next to the drop cache
+pub const BENEFITS_FILE: &str = "benefits.json";
+
+/// Inventory reads while waiting for Twitch to hand out a drop instance id
+const MAX_INSTANCE_ATTEMPTS: u32 = 6;
+/// `claim_drop` calls before giving up on a drop
+const MAX_CLAIM_ATTEMPTS: u32 = 5;
+/// Inventory reads after a claim before it counts as unconfirmed
+const MAX_VERIFY_ATTEMPTS: u32 = 4;
+const BACKOFF_BASE: Duration = Duration::from_secs(5);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// Where a claim currently stands. Every attempt counter is bounded, so a claim always
+/// ends in `Claimed` or `Failed`. A claim only counts as `Claimed` once the inventory shows it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClaimState {
+    Pending,
+    AwaitingInstanceId { attempt: u32 },
+    Claiming { instance_id: String, attempt: u32 },
+    /// `claims` claim calls went through, waiting for the inventory to mark the drop as claimed
+    Verifying { instance_id: String, claims: u32, attempt: u32 },
+    /// With the reward (benefit) ids the drop granted
+    Claimed(Vec<String>),
+    Failed(String)
+}
+
+#[derive(Debug)]
+pub struct ClaimFailed {
+    pub drop_id: String,
+    pub reason: String
+}
+
+impl std::fmt::Display for ClaimFailed {
+    fn fmt (&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to claim drop {}: {}", self.drop_id, self.reason)
+    }
+}
+
+impl std::error::Error for ClaimFailed {}
+
+/// 5s, 10s, 20s, ... capped at a minute.
+fn backoff (attempt: u32) -> Duration {
+    BACKOFF_BASE.saturating_mul(2u32.saturating_pow(attempt)).min(BACKOFF_MAX)
+}
+
+/// What the inventory says about a drop.
+enum Lookup {
+    /// With the reward (benefit) ids of the drop
+    Claimed(Vec<String>),
+    Claimable(String),
+    /// Listed, but without a drop instance id yet
+    Pending,
+    /// Not in `dropCampaignsInProgress`, which a fully claimed campaign may leave
+    NotListed,
+    /// The inventory could not be read
+    Unknown
+}
+
+async fn lookup_drop (client: &TwitchClient, drop_id: &str) -> Lookup {
+    let inv = match client.get_inventory().await {
+        Ok(inv) => inv,
+        Err(e) => {
+            debug!("Inventory read failed while claiming {drop_id}: {e}");
+            return Lookup::Unknown;
+        }
+    };
+    let time_based = inv.inventory.dropCampaignsInProgress.unwrap_or_default().into_iter()
+        .flat_map(|in_progress| in_progress.timeBasedDrops)
+        .find(|time_based| time_based.id == drop_id);
+    match time_based {
+        Some(time_based) if time_based.self_drop.isClaimed => Lookup::Claimed(time_based.benefitEdges.into_iter().map(|edge| edge.benefit.id).collect()),
+        Some(InventoryTimeBasedDrops { self_drop: InventorySelf { dropInstanceID: Some(instance_id), .. }, .. }) => Lookup::Claimable(instance_id),
+        Some(_) => Lookup::Pending,
+        None => Lookup::NotListed,
+    }
+}
+
+/// Claimed already, claimable with an instance id, or not yet.
+async fn await_instance_id (client: &TwitchClient, drop_id: &str, attempt: u32) -> ClaimState {
+    on_instance_lookup(lookup_drop(client, drop_id).await, attempt)
+}
+
+/// Next state after the `attempt`th inventory read while waiting for the drop instance id.
+fn on_instance_lookup (lookup: Lookup, attempt: u32) -> ClaimState {
+    match lookup {
+        Lookup::Claimed(benefits) => ClaimState::Claimed(benefits),
+        Lookup::Claimable(instance_id) => ClaimState::Claiming { instance_id, attempt: 0 },
+        Lookup::Pending | Lookup::NotListed | Lookup::Unknown => ClaimState::AwaitingInstanceId { attempt: attempt + 1 },
+    }
+}
+
+/// Next state after the `attempt`th inventory read following `claims` claim calls.
+fn on_verify_lookup (drop_id: &str, lookup: Lookup, instance_id: String, claims: u32, attempt: u32) -> ClaimState {
+    match lookup {
+        Lookup::Claimed(benefits) => ClaimState::Claimed(benefits),
+        // Listed but not claimed: the claim did not stick, so it is sent again
+        Lookup::Claimable(_) if claims >= MAX_CLAIM_ATTEMPTS => ClaimState::Failed("claimed but never awarded in the inventory".to_string()),
+        Lookup::Claimable(instance_id) => {
+            tracing::warn!("Claim of {drop_id} is not in the inventory, claiming again");
+            ClaimState::Claiming { instance_id, attempt: claims }
+        },
+        // A campaign leaving the in-progress list says nothing about the claim
+        Lookup::Pending | Lookup::NotListed | Lookup::Unknown => ClaimState::Verifying { instance_id, claims, attempt: attempt + 1 },
+    }
+}
+
+/// Drives a claim from `state` until it is claimed or has failed. Returns the reward ids of the claimed drop.
+async fn run_claim (client: &TwitchClient, drop_id: &str, mut state: ClaimState) -> Result<Vec<String>, ClaimFailed> {
+    loop {
+        debug!("Claim {drop_id}: {state:?}");
+        state = match state {
+            ClaimState::Pending => await_instance_id(client, drop_id, 0).await,
+            ClaimState::AwaitingInstanceId { attempt } if attempt >= MAX_INSTANCE_ATTEMPTS => ClaimState::Failed("no drop instance id in the inventory".to_string()),
+            ClaimState::AwaitingInstanceId { attempt } => {
+                sleep(backoff(attempt)).await;
+                await_instance_id(client, drop_id, attempt).await
+            },
+            ClaimState::Claiming { instance_id, attempt } => match client.claim_drop(&instance_id).await {
+                Ok(_) | Err(ClaimDropError::DropAlreadyClaimed) => ClaimState::Verifying { instance_id, claims: attempt + 1, attempt: 0 },
+                Err(e) if attempt + 1 >= MAX_CLAIM_ATTEMPTS => ClaimState::Failed(e.to_string()),
+                Err(e) => {
+                    tracing::warn!("Claim attempt {} for {drop_id} failed: {e}", attempt + 1);
+                    sleep(backoff(attempt)).await;
+                    ClaimState::Claiming { instance_id, attempt: attempt + 1 }
+                }
+            },
+            ClaimState::Verifying { attempt, .. } if attempt >= MAX_VERIFY_ATTEMPTS => ClaimState::Failed("claim could not be confirmed in the inventory".to_string()),
+            ClaimState::Verifying { instance_id, claims, attempt } => {
+                sleep(backoff(attempt)).await;
+                on_verify_lookup(drop_id, lookup_drop(client, drop_id).await, instance_id, claims, attempt)
+            },
+            ClaimState::Claimed(benefits) => return Ok(benefits),
+            ClaimState::Failed(reason) => return Err(ClaimFailed { drop_id: drop_id.to_string(), reason }),
+        }
+    }
+}
+
+/// Claims a drop that finished progressing, waiting for its instance id to show up in the inventory
+/// and for the claim to be confirmed there. Returns the reward ids of the drop.
+pub async fn claim_drop (client: &TwitchClient, drop_id: &str) -> Result<Vec<String>, ClaimFailed> {
+    run_claim(client, drop_id, ClaimState::Pending).await
+}
+
+/// Marks the drop as being claimed. False if it is claimed already or another claim for it is running.
+async fn begin_claim (drop_id: &str) -> bool {
+    if DROP_CASH.lock().await.contains(drop_id) {
+        return false;
+    }
+    CLAIMING.lock().await.insert(drop_id.to_string())
+}
+
+/// Ends a claim started with `begin_claim`. A successful claim has to be in the cache before this is called.
+async fn end_claim (drop_id: &str) {
+    CLAIMING.lock().await.remove(drop_id);
+}
+
+/// Caches the drop, and its rewards in `BENEFITS_FILE` next to the drop cache.
+async fn cache_claimed (cash_path: &Path, drop_id: &str, benefits: Vec<String>) {
+    let mut cash = DROP_CASH.lock().await;
+    cash.insert(drop_id.to_string());
+    save_cash(cash_path, &cash).await;
+    drop(cash);
+    let mut owned = OWNED_BENEFITS.lock().await;
+    owned.extend(benefits);
+    save_cash(&cash_path.with_file_name(BENEFITS_FILE), &owned).await;
+}
+
+/// Claims the drops the watch slots report as finished, one at a time. Every slot reads the whole
+/// inventory and may report the same drop, it is still claimed only once. Claimed drops are
+/// published on `tx_claimed`. A failed claim is tried again when a slot reports the drop again,
+/// which it does once the inventory shows the drop claimable, and is otherwise left to the claim sweep.
+/// Returns where to report finished drops to.
+pub fn claimer (client: Arc<TwitchClient>, cash_path: PathBuf, tx_claimed: UnboundedSender<String>) -> UnboundedSender<String> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    tokio::spawn(async move {
+        while let Some(drop_id) = rx.recv().await {
+            if !begin_claim(&drop_id).await {
+                continue;
+            }
+            match claim_drop(&client, &drop_id).await {
+                Ok(benefits) => {
+                    info!("Drop claimed and verified: {drop_id}");
+                    cache_claimed(&cash_path, &drop_id, benefits).await;
+                    tx_claimed.send(drop_id.clone()).unwrap_or_else(|e| tracing::error!("Failed to publish claimed drop: {e}"));
+                },
+                Err(e) => tracing::error!("{e}"),
+            }
+            end_claim(&drop_id).await;
+        }
+    });
+    tx
+}
+
+pub async fn save_cash (cash_path: &Path, cash: &HashSet<String>) {
+    let cash_string_writer = serde_json::to_string_pretty(cash).unwrap();
+    if let Err(e) = fs::write(cash_path, cash_string_writer).await {
+        tracing::error!("Failed to save {}: {e}", cash_path.display());
+    }
+}
+
+/// A drop that reached its watch time has a drop instance id but is not claimed yet.
+fn is_claimable (time_based: &InventoryTimeBasedDrops) -> bool {
+    !time_based.self_drop.isClaimed && time_based.self_drop.dropInstanceID.is_some()
+}
+
+/// Claims every earned but unclaimed drop in the inventory, for every game, and publishes them on `tx_claimed`.
+/// Returns the ids of the drops that were claimed.
+pub async fn sweep_claimable (client: &TwitchClient, cash_path: &Path, tx_claimed: &UnboundedSender<String>) -> Result<Vec<String>, TwitchError> {
+    let inv = client.get_inventory().await?;
+    let mut claimed = Vec::new();
+    for in_progress in inv.inventory.dropCampaignsInProgress.unwrap_or_default() {
+        for time_based in in_progress.timeBasedDrops.iter().filter(|time_based| is_claimable(time_based)) {
+            let Some(instance_id) = &time_based.self_drop.dropInstanceID else {
+                continue;
+            };
+            // The claimer may be working on the same drop
+            if !begin_claim(&time_based.id).await {
+                continue;
+            }
+            match run_claim(client, &time_based.id, ClaimState::Claiming { instance_id: instance_id.clone(), attempt: 0 }).await {
+                Ok(benefits) => {
+                    info!("Sweep claimed {} from {} ({})", time_based.name, in_progress.name, time_based.id);
+                    cache_claimed(cash_path, &time_based.id, benefits).await;
+                    tx_claimed.send(time_based.id.clone()).unwrap_or_else(|e| tracing::error!("Failed to publish claimed drop: {e}"));
+                    claimed.push(time_based.id.clone());
+                },
+                Err(e) => tracing::error!("Sweep: {e}"),
+            }
+            end_claim(&time_based.id).await;
+        }
+    }
+    Ok(claimed)
+}
+
+/// Repeats the claim sweep every `interval` in the background, and right after a resume.
+pub async fn claim_sweeper (client: Arc<TwitchClient>, cash_path: PathBuf, interval: Duration, tx_claimed: UnboundedSender<String>, mut rx_resume: watch::Receiver<u64>) {
+    tokio::spawn(async move {
+        loop {
+            sleep_or_resume(interval, &mut rx_resume).await;
+            if let Err(e) = sweep_claimable(&client, &cash_path, &tx_claimed).await {
+                tracing::error!("Claim sweep failed: {e}");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{BACKOFF_MAX, ClaimState, Lookup, MAX_CLAIM_ATTEMPTS, backoff, on_instance_lookup, on_verify_lookup};
+
+    #[test]
+    fn backoff_doubles_up_to_a_minute () {
+        assert_eq!(backoff(0), Duration::from_secs(5));
+        assert_eq!(backoff(1), Duration::from_secs(10));
+        assert_eq!(backoff(3), Duration::from_secs(40));
+        assert_eq!(backoff(4), BACKOFF_MAX);
+        assert_eq!(backoff(u32::MAX), BACKOFF_MAX);
+    }
+
+    #[test]
+    fn instance_lookup_waits_until_the_drop_is_claimable () {
+        assert_eq!(on_instance_lookup(Lookup::Claimable("instance".to_string()), 3), ClaimState::Claiming { instance_id: "instance".to_string(), attempt: 0 });
+        assert_eq!(on_instance_lookup(Lookup::Claimed(vec!["benefit".to_string()]), 0), ClaimState::Claimed(vec!["benefit".to_string()]));
+        for lookup in [Lookup::Pending, Lookup::NotListed, Lookup::Unknown] {
+            assert_eq!(on_instance_lookup(lookup, 2), ClaimState::AwaitingInstanceId { attempt: 3 });
+        }
+    }
+
+    #[test]
+    fn verification_needs_the_inventory_to_show_the_claim () {
+        let verify = |lookup, claims| on_verify_lookup("drop", lookup, "instance".to_string(), claims, 1);
+        assert_eq!(verify(Lookup::Claimed(Vec::new()), 1), ClaimState::Claimed(Vec::new()));
+        // A drop missing from the inventory is no evidence, verification goes on until it runs out of attempts
+        for lookup in [Lookup::Pending, Lookup::NotListed, Lookup::Unknown] {
+            assert_eq!(verify(lookup, 1), ClaimState::Verifying { instance_id: "instance".to_string(), claims: 1, attempt: 2 });
+        }
+    }
+
+    #[test]
+    fn unawarded_claim_is_sent_again_a_bounded_number_of_times () {
+        let verify = |claims| on_verify_lookup("drop", Lookup::Claimable("new".to_string()), "instance".to_string(), claims, 0);
+        assert_eq!(verify(1), ClaimState::Claiming { instance_id: "new".to_string(), attempt: 1 });
+        assert!(matches!(verify(MAX_CLAIM_ATTEMPTS), ClaimState::Failed(_)));
+    }
+}
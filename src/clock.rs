@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::{sync::watch::{self, Receiver}, time::sleep};
+use tracing::info;
+
+const TICK: Duration = Duration::from_secs(10);
+/// Wall-clock time that may pass beyond a tick before it counts as a suspend
+const SUSPEND_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// Detects suspend/resume (laptop sleep, paused VM): a tick takes far longer on the wall clock
+/// than it was scheduled for, since nothing runs while the machine sleeps. The tick length is the
+/// reference rather than the monotonic clock, which keeps counting during sleep on Windows
+/// (QueryPerformanceCounter) but not on Linux or macOS. Every detected resume bumps the counter in the channel.
+pub fn resume_watcher () -> Receiver<u64> {
+    let (tx, rx) = watch::channel(0);
+    tokio::spawn(async move {
+        loop {
+            let wall = Utc::now();
+            sleep(TICK).await;
+            let wall_elapsed = (Utc::now() - wall).to_std().unwrap_or_default();
+            if wall_elapsed > TICK + SUSPEND_THRESHOLD {
+                info!("Resumed after about {} min of suspend", (wall_elapsed - TICK).as_secs().div_ceil(60));
+                tx.send_modify(|resumes| *resumes += 1);
+            }
+        }
+    });
+    rx
+}
+
+/// Sleeps for `duration` unless a resume is detected first. Returns true on resume.
+pub async fn sleep_or_resume (duration: Duration, rx_resume: &mut Receiver<u64>) -> bool {
+    tokio::select! {
+        _ = sleep(duration) => false,
+        changed = rx_resume.changed() => {
+            if changed.is_err() {
+                // The watcher is gone, fall back to a plain sleep
+                sleep(duration).await;
+                return false;
+            }
+            true
+        }
+    }
+}
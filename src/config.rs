@@ -0,0 +1,96 @@
+use std::{error::Error, path::{Path, PathBuf}};
+
+use clap::Parser;
+use serde::Deserialize;
+use tokio::fs;
+
+use crate::score::ScoreWeights;
+
+const CONFIG_FILE: &str = "config.toml";
+
+/// Command-line flags. Anything given here overrides `config.toml`.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Args {
+    /// Game to mine: Twitch game id, slug or display name
+    #[arg(long)]
+    pub game: Option<String>,
+    /// Path to the TOML config file (default: data/config.toml)
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Print the feasibility report of the selected game (or of every game) and exit
+    #[arg(long)]
+    pub report: bool,
+}
+
+/// What to do with campaigns whose rewards require a linked publisher account that is not linked.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UnlinkedPolicy {
+    Skip,
+    #[default]
+    Warn,
+    Mine
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Config {
+    /// Game to mine: Twitch game id, slug or display name
+    pub game: Option<String>,
+    /// Campaign ids or names to mine; empty means every campaign of the game
+    pub campaigns: Vec<String>,
+    /// Ordered list of games to work through when `game` is not set
+    pub watchlist: Vec<String>,
+    /// How often the campaign list is re-fetched, in minutes
+    pub refresh_minutes: u64,
+    /// Campaigns that need an account link that is missing: "skip", "warn" or "mine"
+    pub unlinked_campaigns: UnlinkedPolicy,
+    /// Reward wishlist: benefit names or drop ids; empty means every drop
+    pub rewards: Vec<String>,
+    /// How often the inventory is swept for earned but unclaimed drops, in minutes
+    pub claim_sweep_minutes: u64,
+    /// Minutes without any credited progress before the watched channel is treated as stalled
+    pub stall_minutes: u64,
+    /// Minutes a stalled channel is left alone before it is tried again
+    pub stall_cooldown_minutes: u64,
+    /// Preferred stream languages, matched against stream tags (e.g. "English")
+    pub languages: Vec<String>,
+    /// Weights of the channel scoring
+    pub weights: ScoreWeights,
+    /// Number of channels watched at the same time
+    pub watch_slots: usize,
+    /// Optional target per slot: a game (id, slug or name) or a campaign (id or name).
+    /// Games listed here are mined alongside `game`; slots without a target take the best channel left.
+    pub slot_targets: Vec<String>,
+    /// Minutes a slot stays on a channel before it may switch to a better one
+    pub min_dwell_minutes: u64,
+    /// Score a better channel has to win by before a slot switches to it
+    pub switch_margin: f64,
+}
+
+impl Default for Config {
+    fn default () -> Self {
+        Config { game: None, campaigns: Vec::new(), watchlist: Vec::new(), refresh_minutes: 30, unlinked_campaigns: UnlinkedPolicy::default(), rewards: Vec::new(), claim_sweep_minutes: 60, stall_minutes: 10, stall_cooldown_minutes: 30, languages: Vec::new(), weights: ScoreWeights::default(), watch_slots: 1, slot_targets: Vec::new(), min_dwell_minutes: 10, switch_margin: 1.0 }
+    }
+}
+
+impl Config {
+    /// Loads the config from `path`, or from `data/config.toml` if no path is given.
+    /// A missing default file is not an error, a missing explicit one is.
+    pub async fn load (path: Option<&Path>, home_dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => (home_dir.join(CONFIG_FILE), false),
+        };
+        if !path.exists() {
+            if required {
+                return Err(format!("Config file '{}' not found", path.display()))?;
+            }
+            return Ok(Config::default());
+        }
+        let config_str = fs::read_to_string(&path).await?;
+        let config: Config = toml::from_str(&config_str)?;
+        Ok(config)
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::{HashMap, HashSet};
+
+use serde_json::Value;
+use twitch_gql_rs::structs::TimeBasedDropsCampaignDetails;
+
+/// Dependencies between the time-based drops of one campaign.
+/// A drop only starts progressing once all of its precondition drops are earned.
+pub struct DropGraph {
+    drops: HashMap<String, TimeBasedDropsCampaignDetails>,
+    preconditions: HashMap<String, Vec<String>>
+}
+
+/// `preconditionDrops` comes through as a raw string: a single id, a comma separated list,
+/// or a JSON array of ids / `{ "id": ... }` objects.
+fn parse_preconditions (raw: &str) -> Vec<String> {
+    let id_of = |value: &Value| value.as_str().map(str::to_string).or_else(|| value.get("id").and_then(|id| id.as_str()).map(str::to_string));
+    match serde_json::from_str::<Value>(raw) {
+        Ok(Value::Array(items)) => items.iter().filter_map(id_of).collect(),
+        Ok(value @ Value::Object(_)) => id_of(&value).into_iter().collect(),
+        _ => raw.split(',').map(str::trim).filter(|id| !id.is_empty()).map(str::to_string).collect()
+    }
+}
+
+impl DropGraph {
+    pub fn new (drops: &[TimeBasedDropsCampaignDetails]) -> Self {
+        let preconditions = drops.iter().map(|time_based| (time_based.id.clone(), time_based.preconditionDrops.as_deref().map(parse_preconditions).unwrap_or_default())).collect();
+        let drops = drops.iter().map(|time_based| (time_based.id.clone(), time_based.clone())).collect();
+        DropGraph { drops, preconditions }
+    }
+
+    pub fn preconditions (&self, drop_id: &str) -> &[String] {
+        self.preconditions.get(drop_id).map_or(&[], |pre| pre.as_slice())
+    }
+
+    pub fn name<'a> (&'a self, drop_id: &'a str) -> &'a str {
+        self.drops.get(drop_id).map_or(drop_id, |time_based| time_based.name.as_str())
+    }
+
+    /// The given drops plus every drop they transitively depend on.
+    pub fn with_preconditions<'a> (&self, drop_ids: impl IntoIterator<Item = &'a String>) -> HashSet<String> {
+        let mut needed = HashSet::new();
+        let mut stack: Vec<String> = drop_ids.into_iter().cloned().collect();
+        while let Some(drop_id) = stack.pop() {
+            if needed.insert(drop_id.clone()) {
+                stack.extend(self.preconditions(&drop_id).iter().cloned());
+            }
+        }
+        needed
+    }
+
+    /// Whether the drop can still be earned by watching: every precondition is either
+    /// in `done` or is itself a watchable drop of this campaign that can be earned.
+    pub fn is_reachable (&self, drop_id: &str, done: &HashSet<String>) -> bool {
+        self.eta_minutes(drop_id, done, &HashMap::new()).is_some()
+    }
+
+    /// Minutes of watching until the drop is earned, counting the preconditions that have to
+    /// be earned first. `None` if the drop can never progress.
+    pub fn eta_minutes (&self, drop_id: &str, done: &HashSet<String>, progress: &HashMap<String, u64>) -> Option<u64> {
+        self.eta_inner(drop_id, done, progress, &mut HashSet::new())
+    }
+
+    fn eta_inner (&self, drop_id: &str, done: &HashSet<String>, progress: &HashMap<String, u64>, visiting: &mut HashSet<String>) -> Option<u64> {
+        if done.contains(drop_id) {
+            return Some(0);
+        }
+        let time_based = self.drops.get(drop_id)?;
+        if time_based.requiredSubs > 0 || !visiting.insert(drop_id.to_string()) {
+            return None;
+        }
+        let mut before = 0;
+        for pre in self.preconditions(drop_id) {
+            before = before.max(self.eta_inner(pre, done, progress, visiting)?);
+        }
+        visiting.remove(drop_id);
+        let current = progress.get(drop_id).copied().unwrap_or(0);
+        Some(before + time_based.requiredMinutesWatched.saturating_sub(current))
+    }
+}
+
+/// Whether the drop is on the reward wishlist, by drop id, drop name or benefit name.
+/// An empty wishlist wants everything.
+pub fn is_wanted (time_based: &TimeBasedDropsCampaignDetails, rewards: &[String]) -> bool {
+    rewards.is_empty() || rewards.iter().any(|reward| matches_reward(time_based, reward))
+}
+
+/// Whether one wishlist entry names this drop, by drop id, drop name or benefit name.
+pub fn matches_reward (time_based: &TimeBasedDropsCampaignDetails, reward: &str) -> bool {
+    time_based.id == reward
+        || time_based.name.eq_ignore_ascii_case(reward)
+        || time_based.benefitEdges.iter().any(|edge| edge.benefit.name.eq_ignore_ascii_case(reward))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use twitch_gql_rs::structs::{CampaignDetailsBenefits, CampaignDetailsBenefitsEdges, TimeBasedDropsCampaignDetails};
+
+    use super::{DropGraph, is_wanted, parse_preconditions};
+
+    fn time_based (id: &str, minutes: u64, preconditions: Option<&str>) -> TimeBasedDropsCampaignDetails {
+        TimeBasedDropsCampaignDetails {
+            id: id.to_string(),
+            name: format!("Drop {id}"),
+            requiredMinutesWatched: minutes,
+            preconditionDrops: preconditions.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    fn ids (ids: &[&str]) -> HashSet<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_every_precondition_format () {
+        assert_eq!(parse_preconditions("a"), ["a"]);
+        assert_eq!(parse_preconditions("a, b,"), ["a", "b"]);
+        assert_eq!(parse_preconditions(r#"["a", "b"]"#), ["a", "b"]);
+        assert_eq!(parse_preconditions(r#"[{"id": "a"}, {"id": "b"}]"#), ["a", "b"]);
+        assert_eq!(parse_preconditions(r#"{"id": "a"}"#), ["a"]);
+        assert!(parse_preconditions("").is_empty());
+    }
+
+    #[test]
+    fn chained_eta_adds_up_preconditions () {
+        let graph = DropGraph::new(&[time_based("a", 60, None), time_based("b", 30, Some("a")), time_based("c", 45, None)]);
+        let progress = HashMap::from([("a".to_string(), 20)]);
+        assert_eq!(graph.eta_minutes("b", &HashSet::new(), &progress), Some(70));
+        assert_eq!(graph.eta_minutes("b", &ids(&["a"]), &progress), Some(30));
+        assert_eq!(graph.eta_minutes("c", &HashSet::new(), &progress), Some(45));
+        assert_eq!(graph.eta_minutes("a", &ids(&["a"]), &progress), Some(0));
+    }
+
+    #[test]
+    fn sub_gated_precondition_is_unreachable () {
+        let mut gated = time_based("a", 60, None);
+        gated.requiredSubs = 1;
+        let graph = DropGraph::new(&[gated, time_based("b", 30, Some("a"))]);
+        assert_eq!(graph.eta_minutes("b", &HashSet::new(), &HashMap::new()), None);
+        assert!(!graph.is_reachable("b", &HashSet::new()));
+        // Owning the gated drop already unblocks the chain
+        assert!(graph.is_reachable("b", &ids(&["a"])));
+    }
+
+    #[test]
+    fn cyclic_or_unknown_preconditions_are_unreachable () {
+        let graph = DropGraph::new(&[time_based("a", 10, Some("b")), time_based("b", 10, Some("a")), time_based("c", 10, Some("elsewhere"))]);
+        assert!(!graph.is_reachable("a", &HashSet::new()));
+        assert!(!graph.is_reachable("b", &HashSet::new()));
+        assert!(!graph.is_reachable("c", &HashSet::new()));
+        assert!(graph.is_reachable("c", &ids(&["elsewhere"])));
+    }
+
+    #[test]
+    fn wishlist_expands_to_preconditions () {
+        let graph = DropGraph::new(&[time_based("a", 10, None), time_based("b", 10, Some("a")), time_based("c", 10, Some("b")), time_based("d", 10, None)]);
+        let wanted = ["c".to_string()];
+        assert_eq!(graph.with_preconditions(&wanted), ids(&["a", "b", "c"]));
+    }
+
+    #[test]
+    fn wanted_by_id_name_or_benefit () {
+        let mut drop = time_based("a", 10, None);
+        drop.benefitEdges.push(CampaignDetailsBenefitsEdges { benefit: CampaignDetailsBenefits { name: "Golden Skin".to_string(), ..Default::default() }, ..Default::default() });
+        assert!(is_wanted(&drop, &[]));
+        assert!(is_wanted(&drop, &["a".to_string()]));
+        assert!(is_wanted(&drop, &["drop A".to_string()]));
+        assert!(is_wanted(&drop, &["golden skin".to_string()]));
+        assert!(!is_wanted(&drop, &["b".to_string(), "Silver Skin".to_string()]));
+    }
+}
@@ -1,13 +1,23 @@
-use std::{collections::{BTreeMap, HashMap, HashSet}, error::Error, path::{Path, PathBuf}, sync::Arc, time::Duration};
+use std::{collections::{HashMap, HashSet}, error::Error, path::Path, sync::Arc, time::Duration};
 
-use indicatif::{ProgressBar, ProgressStyle};
-use tokio::{fs, sync::{Notify, broadcast::{self, Receiver, error::{TryRecvError}}, watch::Sender}, time::{Instant, sleep}};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use tokio::{fs, sync::{broadcast::{self, Receiver, error::{TryRecvError}}, mpsc::{self, UnboundedReceiver, UnboundedSender}}, time::sleep};
 use tracing::{info};
 use tracing_appender::rolling;
 use tracing_subscriber::fmt::writer::BoxMakeWriter;
-use twitch_gql_rs::{TwitchClient, client_type::ClientType, structs::{DropCampaigns}};
-
-use crate::{r#static::{Channel, DROP_CASH, retry_backup}, stream::{filter_streams, update_stream}};
+use twitch_gql_rs::{TwitchClient, client_type::ClientType, structs::{DropCampaigns, TimeBasedDropsCampaignDetails}};
+
+use crate::{campaign::{GameCampaigns, InventoryState, apply_campaigns, campaign_refresher, feasibility_report, fetch_campaigns, filter_campaigns, find_game, format_time, local_time, next_watchlist_game, parse_time, plan_campaigns, refresh_delay, remaining_drops, warn_unmatched_rewards, watchlist_transition}, claim::{BENEFITS_FILE, claim_sweeper, claimer, sweep_claimable}, clock::{resume_watcher, sleep_or_resume}, config::{Args, Config}, progress::{ProgressTracker, StallDetector}, reputation::{Sampler, load_reputation, record_offline, record_stall, reputation_saver}, score::DefaultScorer, r#static::{Channel, DROP_CASH, INCOMPLETE_CAMPAIGNS, LAST_CANDIDATE, OWNED_BENEFITS, SUSPECT_CHANNELS, retry_backup}, stream::{SlotTarget, SwitchPolicy, WatchSlot, current_selection, drop_channel, filter_streams, lost_eligibility, update_stream}};
+mod campaign;
+mod claim;
+mod clock;
+mod config;
+mod drops;
+mod progress;
+mod reputation;
+mod score;
 mod r#static;
 mod stream;
 
@@ -15,6 +25,17 @@ const STREAM_SLEEP: u64 = 20;
 
 const MAX_COUNT: u64 = 3;
 
+/// Channel polls between inventory reads when no drop looks finished
+const INVENTORY_POLLS: u64 = 10;
+/// `send_watch` rounds between checks that the watched channel still streams a campaign game
+const STREAM_INFO_POLLS: u64 = 15;
+/// Seconds before campaigns whose details or inventory failed to load are tried again
+const TRACK_RETRY: u64 = 60;
+/// Seconds between checks of the tracked drops against the inventory, for claims that were never published
+const TRACK_RECHECK: u64 = 600;
+/// Refresh periods a watchlist game without live channels is passed over
+const IDLE_REFRESHES: u32 = 3;
+
 async fn create_client (home_dir: &Path) -> Result<TwitchClient, Box<dyn Error>> {
     let path = home_dir.join("save.json");
     if !path.exists() {
@@ -31,105 +52,448 @@ async fn create_client (home_dir: &Path) -> Result<TwitchClient, Box<dyn Error>>
 
 #[tokio::main]
 async fn main () -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
     let file_appender = rolling::never(".", "app.log");
     tracing_subscriber::fmt().with_writer(BoxMakeWriter::new(file_appender)).with_ansi(false).init();
     let home_dir = Path::new("data");
     if !home_dir.exists() {
         fs::create_dir_all(&home_dir).await?;
     }
+    let mut config = Config::load(args.config.as_deref(), home_dir).await?;
+    if args.game.is_some() {
+        config.game = args.game;
+    }
 
     let client = create_client(home_dir).await?;
+    load_cash(&home_dir.join("cash.json")).await;
+    load_benefits(&home_dir.join(BENEFITS_FILE)).await;
+    load_reputation(&home_dir.join("reputation.json")).await;
 
-    let campaign = client.get_campaign().await?;
-    let campaign = campaign.dropCampaigns;
+    let grouped = fetch_campaigns(&client).await?;
 
-    let mut id_to_index = HashMap::new();
-    let mut grouped: BTreeMap<usize, Vec<DropCampaigns>> = BTreeMap::new();
-    let mut next_index: usize = 0;
-    for obj in campaign {
-        if obj.status == "EXPIRED" {
-            continue;
+    for (idx, group) in grouped.iter().enumerate() {
+        println!("{} | {} | {}", idx, group.game.slug, group.game.name);
+        for campaign in &group.campaigns {
+            let link = if !campaign.connecting.isAccountConnected && !campaign.accountLinkURL.is_empty() { format!(", account not linked: {}", campaign.accountLinkURL) } else { String::new() };
+            println!("    - {} (ends {}{})", campaign.name, format_time(&campaign.endAt), link);
         }
+        for campaign in &group.upcoming {
+            println!("    - {} (opens {})", campaign.name, format_time(&campaign.startAt));
+        }
+    }
 
-        let idx = *id_to_index.entry(obj.game.id.clone()).or_insert_with(|| {
-            let i = next_index;
-            next_index += 1;
-            i
-        });
+    if args.report {
+        let reported: Vec<&GameCampaigns> = match &config.game {
+            Some(query) => match find_game(&grouped, query) {
+                Some(group) => vec![group],
+                None => return Err(format!("No active campaigns found for game '{query}'"))?,
+            },
+            None => grouped.iter().collect(),
+        };
+        for group in reported {
+            print_report(&client, group, &config).await?;
+        }
+        return Ok(());
+    }
+
+    let client = Arc::new(client);
+    let cash_path = home_dir.join("cash.json");
+    // Every claimed drop id, so no claim is lost when several drops finish at once
+    let (tx_claimed, rx_claimed) = mpsc::unbounded_channel();
+    let rx_resume = resume_watcher();
+    match sweep_claimable(&client, &cash_path, &tx_claimed).await {
+        Ok(claimed) => info!("Startup sweep claimed {} drop(s)", claimed.len()),
+        Err(e) => tracing::error!("Startup claim sweep failed: {e}"),
+    }
+    claim_sweeper(client.clone(), cash_path, Duration::from_secs(config.claim_sweep_minutes.max(1) * 60), tx_claimed.clone(), rx_resume.clone()).await;
+
+    reputation_saver(home_dir.join("reputation.json")).await;
 
-        grouped.entry(idx).or_default().push(obj);
+    main_logic(client, grouped, home_dir, config, tx_claimed, rx_claimed, rx_resume).await?;
+    Ok(())
+}
+
+async fn print_report (client: &TwitchClient, group: &GameCampaigns, config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut campaigns = filter_campaigns(&group.campaigns, &config.campaigns);
+    campaigns.extend(filter_campaigns(&group.upcoming, &config.campaigns));
+    println!("\n{} | {}", group.game.slug, group.game.name);
+    for line in feasibility_report(client, &campaigns, &config.rewards).await? {
+        info!("{line}");
+        println!("  {line}");
     }
+    Ok(())
+}
 
-    for (id, obj) in &grouped {
-        for i in obj {
-            println!("{} | {}", id, i.game.displayName);
+fn select_game_interactive (grouped: &[GameCampaigns]) -> Result<&GameCampaigns, Box<dyn Error>> {
+    loop {
+        let input: String = dialoguer::Input::new().with_prompt("Select game (number, slug or id)").interact_text()?;
+        let input = input.trim();
+        // The number is only an alias for the listing printed above
+        if let Ok(idx) = input.parse::<usize>() && let Some(group) = grouped.get(idx) {
+            return Ok(group);
         }
+        if let Some(group) = find_game(grouped, input) {
+            return Ok(group);
+        }
+        println!("Unknown game: {input}");
     }
+}
 
-    main_logic(Arc::new(client), grouped, home_dir).await?;
-    Ok(())
+/// Lets the user pick individual rewards of the selected campaigns. Selecting nothing mines every drop.
+async fn select_rewards_interactive (client: &TwitchClient, campaigns: &[DropCampaigns]) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut items = Vec::new();
+    let mut drop_ids = Vec::new();
+    for campaign in campaigns {
+        let campaign_details = client.get_campaign_details(&campaign.id).await?;
+        for time_based in campaign_details.timeBasedDrops.iter().filter(|time_based| time_based.requiredSubs == 0) {
+            let benefits: Vec<&str> = time_based.benefitEdges.iter().map(|edge| edge.benefit.name.as_str()).collect();
+            items.push(format!("{}: {} ({})", campaign.name, time_based.name, benefits.join(", ")));
+            drop_ids.push(time_based.id.clone());
+        }
+    }
+    if items.is_empty() {
+        return Ok(Vec::new());
+    }
+    let selected = dialoguer::MultiSelect::new().with_prompt("Select rewards (space to toggle, enter with none selected mines everything)").items(&items).interact()?;
+    Ok(selected.into_iter().map(|idx| drop_ids[idx].clone()).collect())
 }
 
-async fn main_logic (client: Arc<TwitchClient>, grouped: BTreeMap<usize, Vec<DropCampaigns>>, home_dir: &Path) -> Result<(), Box<dyn Error>> {
-    let input: usize = dialoguer::Input::new().with_prompt("Select game").interact_text()?;
-    if let Some(current_campaigns) = grouped.get(&input) {
+/// Resolves `config.slot_targets` to one target per watch slot, plus the games they name
+/// that have to be mined for the slot to have anything to watch.
+fn resolve_slot_targets<'a> (grouped: &'a [GameCampaigns], config: &Config) -> (Vec<SlotTarget>, Vec<&'a GameCampaigns>) {
+    let slots = config.watch_slots.max(1);
+    if config.slot_targets.len() > slots {
+        tracing::warn!("{} slot targets for {} watch slot(s), ignoring the rest", config.slot_targets.len(), slots);
+    }
+    let mut targets = vec![SlotTarget::Any; slots];
+    let mut games = Vec::new();
+    for (target, query) in targets.iter_mut().zip(&config.slot_targets) {
+        if let Some(group) = find_game(grouped, query) {
+            *target = SlotTarget::Game(group.game.id.clone());
+            games.push(group);
+            continue;
+        }
+        let campaign = grouped.iter().find_map(|group| group.campaigns.iter().chain(&group.upcoming)
+            .find(|campaign| campaign.id == *query || campaign.name.eq_ignore_ascii_case(query))
+            .map(|campaign| (group, campaign)));
+        match campaign {
+            Some((group, campaign)) => {
+                *target = SlotTarget::Campaign(campaign.id.clone());
+                games.push(group);
+            },
+            None => tracing::warn!("Slot target '{query}' matches no game or campaign, the slot takes any channel"),
+        }
+    }
+    (targets, games)
+}
 
-        let (tx_watch, mut rx_watch) = tokio::sync::watch::channel(String::new());
-        let drop_campaigns = Arc::new(current_campaigns.clone());
-        
-        let drop_cash_dir = home_dir.join("cash.json");
+async fn main_logic (client: Arc<TwitchClient>, grouped: Vec<GameCampaigns>, home_dir: &Path, mut config: Config, tx_claimed: UnboundedSender<String>, mut rx_claimed: UnboundedReceiver<String>, rx_resume: tokio::sync::watch::Receiver<u64>) -> Result<(), Box<dyn Error>> {
+    let watchlist_mode = config.game.is_none() && !config.watchlist.is_empty();
+    let mut current_campaigns = Vec::new();
+    let mut upcoming_campaigns = Vec::new();
+    let mut game_ids = Vec::new();
+    // Watchlist mode mines one game at a time, so games pinned to a slot would never be mined
+    let (slot_targets, slot_games) = if watchlist_mode {
+        if !config.slot_targets.is_empty() {
+            tracing::warn!("slot_targets are ignored in watchlist mode, every slot takes any channel");
+        }
+        (vec![SlotTarget::Any; config.watch_slots.max(1)], Vec::new())
+    } else {
+        resolve_slot_targets(&grouped, &config)
+    };
+    if watchlist_mode && !config.rewards.is_empty() {
+        let watched: Vec<DropCampaigns> = config.watchlist.iter().filter_map(|query| find_game(&grouped, query))
+            .flat_map(|group| group.campaigns.iter().chain(&group.upcoming)).cloned().collect();
+        warn_unmatched_rewards(&client, &watched, &config.rewards).await;
+    }
+    if !watchlist_mode {
+        let selected = match &config.game {
+            Some(query) => match find_game(&grouped, query) {
+                Some(group) => {
+                    print_report(&client, group, &config).await?;
+                    group
+                },
+                None => return Err(format!("No active campaigns found for game '{query}'"))?,
+            },
+            None => loop {
+                let group = select_game_interactive(&grouped)?;
+                print_report(&client, group, &config).await?;
+                if dialoguer::Confirm::new().with_prompt(format!("Mine {}?", group.game.name)).default(true).interact()? {
+                    break group;
+                }
+            },
+        };
+        // Games pinned to a watch slot are mined in parallel with the selected one
+        let mut games = vec![selected];
+        for group in slot_games {
+            if !games.iter().any(|game| game.game.id == group.game.id) {
+                print_report(&client, group, &config).await?;
+                games.push(group);
+            }
+        }
+        for group in &games {
+            current_campaigns.extend(filter_campaigns(&group.campaigns, &config.campaigns));
+            upcoming_campaigns.extend(filter_campaigns(&group.upcoming, &config.campaigns));
+        }
+        let game_names: Vec<String> = games.iter().map(|group| group.game.to_string()).collect();
+        if current_campaigns.is_empty() && upcoming_campaigns.is_empty() {
+            return Err(format!("None of the configured campaigns are active for {}", game_names.join(", ")))?;
+        }
+        if config.game.is_none() && config.rewards.is_empty() {
+            let mut selectable = current_campaigns.clone();
+            selectable.extend(upcoming_campaigns.iter().cloned());
+            config.rewards = select_rewards_interactive(&client, &selectable).await?;
+        }
+        if !config.rewards.is_empty() {
+            info!("Reward wishlist: {}", config.rewards.join(", "));
+            let mut selected = current_campaigns.clone();
+            selected.extend(upcoming_campaigns.iter().cloned());
+            warn_unmatched_rewards(&client, &selected, &config.rewards).await;
+        }
+        current_campaigns = plan_campaigns(&client, &current_campaigns, &config).await;
+        info!("Mining {} campaign(s) of {}", current_campaigns.len(), game_names.join(", "));
+        for (idx, campaign) in current_campaigns.iter().enumerate() {
+            info!("{}. {} ({}), ends {}", idx + 1, campaign.name, campaign.id, format_time(&campaign.endAt));
+        }
+        for campaign in &upcoming_campaigns {
+            info!("Queued {} ({}), opens {}", campaign.name, campaign.id, format_time(&campaign.startAt));
+        }
+        game_ids = games.iter().map(|group| group.game.id.clone()).collect();
+    }
+
+    let (tx_campaigns, mut rx_campaigns) = tokio::sync::watch::channel(current_campaigns);
+    let (tx_upcoming, mut rx_upcoming) = tokio::sync::watch::channel(upcoming_campaigns);
+    let refresh = Duration::from_secs(config.refresh_minutes.max(1) * 60);
+    
+    let drop_cash_dir = home_dir.join("cash.json");
 
+    let tx_claim = claimer(client.clone(), drop_cash_dir, tx_claimed);
+    let bars = MultiProgress::new();
+
+    // Every slot watches its own channel and tracks its own drop progress
+    let mut slots = Vec::new();
+    for (id, target) in slot_targets.into_iter().enumerate() {
         let (tx, rx1) = broadcast::channel(100);
         let rx2 = tx.subscribe();
-
-        let notify = Arc::new(Notify::new());
-
-        watch_sync(client.clone(), rx1, notify.clone()).await;
-        info!("Watch synchronization task has been successfully initiated");
-        drop_sync(client.clone(), tx_watch, drop_cash_dir, rx2, notify.clone()).await;
-        info!("Drop progress tracker is active");
-        filter_streams(client.clone(), drop_campaigns.clone()).await;
-        info!("Stream filtering has begun");
-        update_stream(drop_campaigns, tx, notify).await;
-        info!("Stream priority updated");
-
-        for campaign in current_campaigns {
-            let mut campaign_details = client.get_campaign_details(&campaign.id).await?;
-
-            let drop_ids_cache = DROP_CASH.lock().await.clone();    
-            for drop_id_cache in drop_ids_cache {
-                let deleted_time_based = campaign_details.timeBasedDrops.iter().filter(|time_based| time_based.id == drop_id_cache).map(|time_based| time_based.id.clone()).collect::<Vec<String>>();
-                for delete in deleted_time_based {
-                    if let Some(pos) = campaign_details.timeBasedDrops.iter().position(|time_based| time_based.id == delete) {
-                        campaign_details.timeBasedDrops.remove(pos);
-                    }
+        let (tx_leave, rx_leave) = mpsc::unbounded_channel();
+        info!("Watch slot {id}: {target:?}");
+        watch_sync(client.clone(), rx1, rx_campaigns.clone(), SlotSync { id, tx_leave: tx_leave.clone(), rx_resume: rx_resume.clone() }).await;
+        drop_sync(client.clone(), tx_claim.clone(), rx2, SlotSync { id, tx_leave: tx_leave.clone(), rx_resume: rx_resume.clone() }, &config, bars.add(ProgressBar::new(1))).await;
+        slots.push(WatchSlot { id, target, tx_now_watch: tx, rx_leave });
+    }
+    info!("Watch synchronization and drop progress tracking started for {} slot(s)", slots.len());
+    filter_streams(client.clone(), rx_campaigns.clone(), rx_resume.clone()).await;
+    info!("Stream filtering has begun");
+    let scorer = Arc::new(DefaultScorer::new(config.weights.clone(), config.languages.clone()));
+    let policy = SwitchPolicy { min_dwell: Duration::from_secs(config.min_dwell_minutes * 60), margin: config.switch_margin };
+    update_stream(rx_campaigns.clone(), slots, scorer, policy).await;
+    info!("Stream priority updated");
+
+    if watchlist_mode {
+        run_watchlist(&client, grouped, &config, tx_campaigns, &mut rx_claimed, rx_resume).await
+    } else {
+        campaign_refresher(client.clone(), game_ids, config.clone(), tx_campaigns, tx_upcoming, refresh, rx_resume.clone()).await;
+        info!("Campaign refresher started");
+        let mut finished = HashSet::new();
+        'mining: loop {
+            finished.extend(mine_campaigns(&client, rx_campaigns.clone(), &mut rx_claimed, &config.rewards).await?);
+            loop {
+                // A refresh may have published new campaigns while the last ones were finishing
+                if rx_campaigns.borrow_and_update().iter().any(|campaign| !finished.contains(&campaign.id)) {
+                    continue 'mining;
+                }
+                // Upcoming campaigns may open, or be dropped when they turn out to have nothing to earn
+                let next_start = rx_upcoming.borrow_and_update().iter().filter_map(|campaign| parse_time(&campaign.startAt)).min();
+                let Some(next_start) = next_start else {
+                    break 'mining;
+                };
+                info!("Waiting for the next campaign to open at {}", local_time(next_start));
+                tokio::select! {
+                    changed = rx_campaigns.changed() => changed?,
+                    changed = rx_upcoming.changed() => changed?
                 }
             }
+        }
+        info!("No wanted drops left in the selected campaigns");
+        Ok(())
+    }
+}
 
-            loop {
-                rx_watch.changed().await.unwrap();
-                let drop_id = rx_watch.borrow();
-                if drop_id.is_empty() {
-                    sleep(Duration::from_secs(10)).await;
-                    continue;
+/// Works through `config.watchlist` in order, re-fetching campaigns every `refresh_minutes` and after a resume.
+/// A game that had no channel to watch for a whole refresh period is passed over for `IDLE_REFRESHES` periods.
+async fn run_watchlist (client: &TwitchClient, mut grouped: Vec<GameCampaigns>, config: &Config, tx_campaigns: tokio::sync::watch::Sender<Vec<DropCampaigns>>, rx_claimed: &mut UnboundedReceiver<String>, mut rx_resume: tokio::sync::watch::Receiver<u64>) -> Result<(), Box<dyn Error>> {
+    let refresh = Duration::from_secs(config.refresh_minutes.max(1) * 60);
+    // Game id to the time it is tried again
+    let mut idle: HashMap<String, DateTime<Utc>> = HashMap::new();
+    let mut mining_since = Utc::now();
+    loop {
+        idle.retain(|_, until| *until > Utc::now());
+        let skip: HashSet<String> = idle.keys().cloned().collect();
+        // With every game idle the first one with drops is still better than nothing
+        let next = match next_watchlist_game(client, &grouped, config, &skip).await {
+            None if !skip.is_empty() => next_watchlist_game(client, &grouped, config, &HashSet::new()).await,
+            next => next,
+        };
+        match next {
+            Some((game, campaigns)) => {
+                if tx_campaigns.borrow().first().map(|campaign| &campaign.game.id) != Some(&game.id) {
+                    mining_since = Utc::now();
+                    info!("Watchlist: mining {} campaign(s) of {}", campaigns.len(), game);
+                    match feasibility_report(client, &campaigns, &config.rewards).await {
+                        Ok(lines) => lines.iter().for_each(|line| info!("{line}")),
+                        Err(e) => tracing::error!("Failed to build feasibility report: {e}"),
+                    }
                 }
-                if campaign_details.timeBasedDrops.is_empty() {
-                    break;
+                apply_campaigns(&tx_campaigns, campaigns);
+                let delay = refresh_delay(refresh, watchlist_transition(&grouped, &config.watchlist));
+                tokio::select! {
+                    result = mine_campaigns(client, tx_campaigns.subscribe(), rx_claimed, &config.rewards) => match result {
+                        Ok(_) => info!("Watchlist: no drops left for {}", game),
+                        Err(e) => {
+                            tracing::error!("Watchlist: mining {} stopped, retrying in {} min: {e}", game, delay.as_secs().div_ceil(60));
+                            sleep_or_resume(delay, &mut rx_resume).await;
+                        }
+                    },
+                    _ = sleep_or_resume(delay, &mut rx_resume) => {
+                        let last_candidate = LAST_CANDIDATE.lock().await.map_or(mining_since, |last| last.max(mining_since));
+                        if (Utc::now() - last_candidate).to_std().unwrap_or_default() >= refresh {
+                            info!("Watchlist: no live channel for {} in {} min, moving on", game, refresh.as_secs() / 60);
+                            idle.insert(game.id.clone(), Utc::now() + refresh * IDLE_REFRESHES);
+                        }
+                    }
                 }
-                if let Some(pos) = campaign_details.timeBasedDrops.iter().position(|time_based| time_based.id == *drop_id) {
-                    campaign_details.timeBasedDrops.remove(pos);
+            },
+            None => {
+                let delay = refresh_delay(refresh, watchlist_transition(&grouped, &config.watchlist));
+                info!("Watchlist: no active campaigns, checking again in {} min", delay.as_secs().div_ceil(60));
+                apply_campaigns(&tx_campaigns, Vec::new());
+                sleep_or_resume(delay, &mut rx_resume).await;
+            }
+        }
+        match fetch_campaigns(client).await {
+            Ok(new_grouped) => grouped = new_grouped,
+            Err(e) => tracing::error!("Failed to refresh campaigns: {e}"),
+        }
+    }
+}
+
+/// Tracks the remaining (wanted) drops of every published campaign until none are left.
+/// Campaigns added by a refresh start being tracked, removed ones are dropped, and campaigns
+/// that failed to load are tried again after `TRACK_RETRY` while the others keep being tracked.
+/// Drops the inventory shows as earned are dropped every `TRACK_RECHECK`, even if their claim was not confirmed in time.
+/// Returns the ids of the campaigns that were finished.
+async fn mine_campaigns (client: &TwitchClient, mut rx_campaigns: tokio::sync::watch::Receiver<Vec<DropCampaigns>>, rx_claimed: &mut UnboundedReceiver<String>, rewards: &[String]) -> Result<HashSet<String>, Box<dyn Error>> {
+    let mut tracked: HashMap<String, Vec<TimeBasedDropsCampaignDetails>> = HashMap::new();
+    let mut finished = HashSet::new();
+    let mut untracked = HashSet::new();
+    let mut campaigns_changed = true;
+    loop {
+        if campaigns_changed {
+            let campaigns = rx_campaigns.borrow_and_update().clone();
+            tracked.retain(|id, _| campaigns.iter().any(|campaign| campaign.id == *id));
+            let new: Vec<&DropCampaigns> = campaigns.iter().filter(|campaign| !tracked.contains_key(&campaign.id) && !finished.contains(&campaign.id)).collect();
+            untracked = track_campaigns(client, &new, &mut tracked, rewards).await;
+            campaigns_changed = false;
+        }
+
+        tracked.retain(|id, drops| {
+            if drops.is_empty() {
+                finished.insert(id.clone());
+            }
+            !drops.is_empty()
+        });
+        // Channel ranking only counts campaigns that still have drops left, or may have
+        *INCOMPLETE_CAMPAIGNS.lock().await = Some(tracked.keys().chain(&untracked).cloned().collect());
+        if tracked.is_empty() && untracked.is_empty() {
+            return Ok(finished);
+        }
+
+        tokio::select! {
+            drop_id = rx_claimed.recv() => {
+                let Some(drop_id) = drop_id else {
+                    return Err("Claimed drop channel closed")?;
+                };
+                for drops in tracked.values_mut() {
+                    drops.retain(|time_based| time_based.id != drop_id);
                 }
+            },
+            changed = rx_campaigns.changed() => {
+                changed?;
+                campaigns_changed = true;
+            },
+            _ = sleep(Duration::from_secs(if untracked.is_empty() { TRACK_RECHECK } else { TRACK_RETRY })) => {
+                campaigns_changed = !untracked.is_empty();
+                recheck_drops(client, &mut tracked).await;
             }
-            
         }
     }
-    Ok(())
 }
 
-async fn watch_sync (client: Arc<TwitchClient>, mut rx: Receiver<Channel>, notify: Arc<Notify>) {
+/// Removes the tracked drops the inventory shows as earned.
+async fn recheck_drops (client: &TwitchClient, tracked: &mut HashMap<String, Vec<TimeBasedDropsCampaignDetails>>) {
+    let inventory = match InventoryState::load(client).await {
+        Ok(inventory) => inventory,
+        Err(e) => {
+            tracing::error!("Failed to load inventory to re-check tracked drops: {e}");
+            return;
+        }
+    };
+    for drops in tracked.values_mut() {
+        let done = inventory.done_drops(drops).await;
+        drops.retain(|time_based| {
+            if done.contains(&time_based.id) {
+                info!("{} ({}) shows as earned in the inventory", time_based.name, time_based.id);
+            }
+            !done.contains(&time_based.id)
+        });
+    }
+}
+
+/// Adds the remaining drops of `campaigns` to `tracked`. Returns the ids of the campaigns that could not be loaded.
+async fn track_campaigns (client: &TwitchClient, campaigns: &[&DropCampaigns], tracked: &mut HashMap<String, Vec<TimeBasedDropsCampaignDetails>>, rewards: &[String]) -> HashSet<String> {
+    if campaigns.is_empty() {
+        return HashSet::new();
+    }
+    let inventory = match InventoryState::load(client).await {
+        Ok(inventory) => inventory,
+        Err(e) => {
+            tracing::error!("Failed to load inventory, tracking {} campaign(s) in {TRACK_RETRY}s: {e}", campaigns.len());
+            return campaigns.iter().map(|campaign| campaign.id.clone()).collect();
+        }
+    };
+    let mut failed = HashSet::new();
+    for campaign in campaigns {
+        match client.get_campaign_details(&campaign.id).await {
+            Ok(campaign_details) => {
+                let drops = remaining_drops(&campaign_details, &inventory, rewards).await;
+                info!("Tracking {} ({}): {} drop(s) remaining", campaign.name, campaign.id, drops.len());
+                tracked.insert(campaign.id.clone(), drops);
+            },
+            Err(e) => {
+                tracing::error!("Failed to load campaign {} ({}), retrying in {TRACK_RETRY}s: {e}", campaign.name, campaign.id);
+                failed.insert(campaign.id.clone());
+            }
+        }
+    }
+    failed
+}
+
+/// What the watch and drop tasks of one watch slot share.
+struct SlotSync {
+    id: usize,
+    /// Asks the slot to leave a channel, by channel id
+    tx_leave: UnboundedSender<String>,
+    rx_resume: tokio::sync::watch::Receiver<u64>
+}
+
+async fn watch_sync (client: Arc<TwitchClient>, mut rx: Receiver<Channel>, rx_campaigns: tokio::sync::watch::Receiver<Vec<DropCampaigns>>, slot: SlotSync) {
+    let SlotSync { id: slot, tx_leave, mut rx_resume } = slot;
     tokio::spawn(async move {
         let mut old_stream_name = String::new();
         let mut stream_id = String::new();
+        let mut polls: u64 = 0;
+        // Set once the watched channel was found offline, so an interruption counts once
+        let mut offline = false;
 
         let mut watching = rx.recv().await.unwrap();
         loop {
@@ -140,59 +504,91 @@ async fn watch_sync (client: Arc<TwitchClient>, mut rx: Receiver<Channel>, notif
             };
 
             if old_stream_name.is_empty() || old_stream_name != watching.channel_login {
-                info!("Now actively watching channel {}", watching.channel_login);
+                info!("Slot {slot}: now actively watching channel {}", watching.channel_login);
                 old_stream_name = watching.channel_login.clone();
                 stream_id.clear();
+                offline = false;
             }
 
-            if stream_id.is_empty() {
+            if stream_id.is_empty() || polls.is_multiple_of(STREAM_INFO_POLLS) {
                 let stream = retry!(client.get_stream_info(&watching.channel_login));
                 if let Some(id) = stream.stream {
-                    stream_id = id.id
+                    stream_id = id.id;
+                    offline = false;
                 } else {
-                    notify.notify_one();
+                    if !offline {
+                        offline = true;
+                        record_offline(&watching).await;
+                        tx_leave.send(watching.channel_id.clone()).unwrap_or_else(|e| tracing::error!("{e}"));
+                    }
                     sleep(Duration::from_secs(STREAM_SLEEP)).await;
                     continue;
                 }
-            }
-
-            match client.send_watch(&watching.channel_login, &stream_id, &watching.channel_id).await {
-                Ok(_) => {
-                    sleep(Duration::from_secs(STREAM_SLEEP)).await
-                },
-                Err(e) => {
-                    tracing::error!("{e}");
+                // Directory channels may move to another game while staying live
+                let campaigns = rx_campaigns.borrow().clone();
+                if let Some(reason) = lost_eligibility(&client, &watching, &stream.broadcastSettings.game, &campaigns).await {
+                    info!("Slot {slot}: leaving {}, it {reason}", watching.channel_login);
+                    drop_channel(&watching.channel_id).await;
+                    stream_id.clear();
                     sleep(Duration::from_secs(STREAM_SLEEP)).await;
+                    continue;
                 }
             }
+
+            if let Err(e) = client.send_watch(&watching.channel_login, &stream_id, &watching.channel_id).await {
+                tracing::error!("{e}");
+            }
+            polls += 1;
+            if sleep_or_resume(Duration::from_secs(STREAM_SLEEP), &mut rx_resume).await {
+                // The stream may have ended while the machine slept, check it is still live
+                info!("Re-checking {} after resume", watching.channel_login);
+                stream_id.clear();
+            }
         }
     });
 }
 
-async fn drop_sync (client: Arc<TwitchClient>, tx: Sender<String>, cash_path: PathBuf, mut rx_watch: broadcast::Receiver<Channel>, notify: Arc<Notify>) {
+async fn load_cash (cash_path: &Path) {
+    if !cash_path.exists() {
+        retry!(fs::write(&cash_path, "[]"));
+    } else {
+        let mut cash = DROP_CASH.lock().await;
+        let cash_str = retry!(fs::read_to_string(&cash_path));
+        let cash_vec: HashSet<String> = serde_json::from_str(&cash_str).unwrap();  
+        *cash = cash_vec;
+        drop(cash);
+    }
+}
+
+async fn load_benefits (benefits_path: &Path) {
+    if !benefits_path.exists() {
+        return;
+    }
+    let benefits_str = retry!(fs::read_to_string(&benefits_path));
+    match serde_json::from_str::<HashSet<String>>(&benefits_str) {
+        Ok(benefits) => *OWNED_BENEFITS.lock().await = benefits,
+        Err(e) => tracing::error!("Failed to parse {}: {e}", benefits_path.display()),
+    }
+}
+
+async fn drop_sync (client: Arc<TwitchClient>, tx_claim: UnboundedSender<String>, mut rx_watch: broadcast::Receiver<Channel>, slot: SlotSync, config: &Config, bar: ProgressBar) {
+    let SlotSync { id: slot, tx_leave, mut rx_resume } = slot;
+    let stall_window = Duration::from_secs(config.stall_minutes.max(1) * 60);
+    let stall_cooldown = Duration::from_secs(config.stall_cooldown_minutes * 60);
     tokio::spawn(async move {
-        let mut end_time = Instant::now() + Duration::from_secs(60*60);
-        let mut old_drop = String::new();
+        let mut tracker = ProgressTracker::default();
+        let mut stall = StallDetector::new(stall_window);
+        let mut sampler = Sampler::new();
 
         //bar
-        let bar = ProgressBar::new(1);
-        bar.set_style(ProgressStyle::with_template("[{bar:40.cyan/blue}] {percent:.1}% ({pos}/{len} min) {msg}").unwrap());
+        bar.set_style(ProgressStyle::with_template("{prefix} [{bar:40.cyan/blue}] {percent:.1}% ({pos}/{len} min) {msg}").unwrap());
+        bar.set_prefix(format!("Slot {slot}"));
         bar.set_message("Initialization...");
         bar.enable_steady_tick(Duration::from_millis(500));
 
-        if !cash_path.exists() {
-            retry!(fs::write(&cash_path, "[]"));
-        } else {
-            let mut cash = DROP_CASH.lock().await;
-            let cash_str = retry!(fs::read_to_string(&cash_path));
-            let cash_vec: HashSet<String> = serde_json::from_str(&cash_str).unwrap();  
-            *cash = cash_vec;
-            drop(cash);
-        }
-
-        let tolerance = Duration::from_secs(5 * 60);
-
         let mut count = 0;
+        let mut polls = 0;
+        let mut resumed = false;
 
         let mut watching = rx_watch.recv().await.unwrap();
         loop {
@@ -204,78 +600,70 @@ async fn drop_sync (client: Arc<TwitchClient>, tx: Sender<String>, cash_path: Pa
                 Err(TryRecvError::Closed) => break,
                 Err(_) => {}
             }
-            let mut cash = DROP_CASH.lock().await;
 
             let drop_progress = retry!(client.get_current_drop_progress_on_channel(&watching.channel_login, &watching.channel_id));
 
             if drop_progress.dropID.is_empty() {
                 count += 1;
                 if count >= MAX_COUNT {
-                    drop(cash);
-                    notify.notify_one();
+                    tx_leave.send(watching.channel_id.clone()).unwrap_or_else(|e| tracing::error!("{e}"));
                     count = 0;
                     continue;
                 } else {
-                    drop(cash);
                     sleep(Duration::from_secs(5)).await;
                     continue;
                 }
             }
 
-            if old_drop.is_empty() {
-                old_drop = drop_progress.dropID.to_string()
+            let switched = tracker.observe_current(&drop_progress);
+            polls += 1;
+            // The inventory carries the claimable signal; read it when a drop may have finished
+            if switched || resumed || tracker.awaiting_confirmation() || polls % INVENTORY_POLLS == 0 {
+                match client.get_inventory().await {
+                    Ok(inv) => tracker.observe_inventory(&inv),
+                    Err(e) => tracing::error!("Failed to load inventory: {e}"),
+                }
             }
 
-            let mut need_update = false;
-
-            if end_time <= Instant::now() || old_drop != drop_progress.dropID && !cash.contains(&drop_progress.dropID) {
-                retry!(claim_drop(&client, &old_drop));
-                info!("Drop claimed: {}", old_drop);
-                tx.send(old_drop.to_string()).unwrap();
-                cash.insert(old_drop.to_string());
-                old_drop = drop_progress.dropID.to_string();
-                need_update = true;
-
-                let cash_string_writer = serde_json::to_string_pretty(&cash.clone()).unwrap();
-                retry!(fs::write(&cash_path, cash_string_writer.clone()));
-                drop(cash);
+            // Claiming is left to the claimer, which claims each drop once for all slots
+            for drop_id in tracker.ready_to_claim() {
+                if DROP_CASH.lock().await.contains(&drop_id) {
+                    tracker.mark_claimed(&drop_id);
+                    continue;
+                }
+                tx_claim.send(drop_id.clone()).unwrap_or_else(|e| tracing::error!("Failed to hand {drop_id} to the claimer: {e}"));
+                tracker.mark_queued(&drop_id);
             }
 
-            bar.set_length(drop_progress.requiredMinutesWatched);
-            bar.set_position(drop_progress.currentMinutesWatched);
-            bar.set_message(format!("DropID: {}", drop_progress.dropID));
+            if let Some((drop_id, progress)) = tracker.current() {
+                bar.set_length(progress.required_minutes);
+                bar.set_position(progress.current_minutes);
+                bar.set_message(format!("DropID: {}", drop_id));
+                // A finished drop earns nothing more, so it says nothing about the channel
+                if !progress.is_complete() {
+                    sampler.sample(&watching, drop_id, progress.current_minutes).await;
+                }
 
-            if end_time <= Instant::now() + tolerance || Instant::now() <= end_time + tolerance && need_update  {
-                let reaming = drop_progress.requiredMinutesWatched.saturating_sub(drop_progress.currentMinutesWatched);
-                end_time = Instant::now() + Duration::from_secs(reaming * 60);
+                if !progress.is_complete() && stall.observe(&watching.channel_id, drop_id, progress.current_minutes) {
+                    tracing::warn!("No progress on {} for {} min, switching away", watching.channel_login, stall_window.as_secs() / 60);
+                    if let Some(selection) = current_selection(slot).await {
+                        tracing::warn!("Stalled channel was {selection}");
+                    }
+                    let until = Utc::now() + stall_cooldown;
+                    SUSPECT_CHANNELS.lock().await.insert(watching.channel_id.clone(), until);
+                    stall.reset();
+                    record_stall(&watching).await;
+                    tx_leave.send(watching.channel_id.clone()).unwrap_or_else(|e| tracing::error!("{e}"));
+                }
             }
 
-            sleep(Duration::from_secs(30)).await;
+            // After a resume the progress is re-read right away, including the inventory
+            resumed = sleep_or_resume(Duration::from_secs(30), &mut rx_resume).await;
+            if resumed {
+                stall.reset();
+                sampler.reset();
+            }
         }
        
     });
 }
-
-async fn claim_drop (client: &Arc<TwitchClient>, drop_progress_id: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
-    loop {
-        let inv = retry!(client.get_inventory());
-        if let Some(campaigns_in_progress) = inv.inventory.dropCampaignsInProgress {
-            for in_progress in campaigns_in_progress {
-                for time_based in in_progress.timeBasedDrops {
-                    if time_based.id == drop_progress_id {
-                        if let Some(id) = time_based.self_drop.dropInstanceID {
-                            loop {
-                                match client.claim_drop(&id).await {
-                                Ok(_) => return Ok(()),
-                                Err(twitch_gql_rs::error::ClaimDropError::DropAlreadyClaimed) => return Ok(()),
-                                Err(e) => tracing::error!("{e}")
-                                }
-                                sleep(Duration::from_secs(5)).await
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
-}
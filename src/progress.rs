@@ -0,0 +1,230 @@
+use std::{collections::HashMap, time::Duration};
+
+use chrono::{DateTime, Utc};
+use twitch_gql_rs::structs::{CurrentDrop, GetInventory};
+
+/// What the server last told us about one drop.
+#[derive(Debug, Clone)]
+pub struct DropProgress {
+    pub current_minutes: u64,
+    pub required_minutes: u64,
+    pub last_seen: DateTime<Utc>,
+    /// The inventory handed out a drop instance id, so the drop can be claimed right now
+    pub claimable: bool,
+    pub claimed: bool,
+    /// Handed to the claimer. Cleared when the inventory turns the drop claimable, so a claim that
+    /// gave up waiting for the drop instance id is tried again
+    pub queued: bool
+}
+
+impl DropProgress {
+    fn new (required_minutes: u64) -> Self {
+        DropProgress { current_minutes: 0, required_minutes, last_seen: Utc::now(), claimable: false, claimed: false, queued: false }
+    }
+
+    pub fn is_complete (&self) -> bool {
+        self.required_minutes > 0 && self.current_minutes >= self.required_minutes
+    }
+}
+
+/// Per-drop progress fed from `get_current_drop_progress_on_channel` and the inventory.
+/// Claim time is decided from the reported minutes and the claimable flag, never from elapsed time.
+#[derive(Default)]
+pub struct ProgressTracker {
+    drops: HashMap<String, DropProgress>,
+    current: Option<String>
+}
+
+impl ProgressTracker {
+    /// Records the drop the watched channel is progressing. Returns true if the server
+    /// switched to a different drop since the last poll.
+    pub fn observe_current (&mut self, drop_progress: &CurrentDrop) -> bool {
+        let entry = self.drops.entry(drop_progress.dropID.clone()).or_insert_with(|| DropProgress::new(drop_progress.requiredMinutesWatched));
+        entry.required_minutes = drop_progress.requiredMinutesWatched;
+        // Never go backwards: the channel endpoint sometimes reports stale minutes
+        entry.current_minutes = entry.current_minutes.max(drop_progress.currentMinutesWatched);
+        entry.last_seen = Utc::now();
+        let switched = self.current.as_deref().is_some_and(|current| current != drop_progress.dropID);
+        self.current = Some(drop_progress.dropID.clone());
+        switched
+    }
+
+    /// Updates every drop the inventory knows about, including drops of other channels.
+    pub fn observe_inventory (&mut self, inv: &GetInventory) {
+        let now = Utc::now();
+        for in_progress in inv.inventory.dropCampaignsInProgress.iter().flatten() {
+            for time_based in &in_progress.timeBasedDrops {
+                let drop_self = &time_based.self_drop;
+                let entry = self.drops.entry(time_based.id.clone()).or_insert_with(|| DropProgress::new(time_based.requiredMinutesWatched));
+                entry.required_minutes = time_based.requiredMinutesWatched;
+                entry.current_minutes = entry.current_minutes.max(drop_self.currentMinutesWatched);
+                entry.claimed = drop_self.isClaimed;
+                let claimable = !drop_self.isClaimed && drop_self.dropInstanceID.is_some();
+                if claimable && !entry.claimable {
+                    entry.queued = false;
+                }
+                entry.claimable = claimable;
+                entry.last_seen = now;
+            }
+        }
+    }
+
+    pub fn current (&self) -> Option<(&str, &DropProgress)> {
+        let drop_id = self.current.as_deref()?;
+        self.drops.get(drop_id).map(|progress| (drop_id, progress))
+    }
+
+    /// A tracked drop reached its required minutes but is not confirmed claimable or claimed yet.
+    pub fn awaiting_confirmation (&self) -> bool {
+        self.drops.values().any(|progress| progress.is_complete() && !progress.claimed && !progress.claimable && !progress.queued)
+    }
+
+    /// Drops that should be claimed now: claimable per the inventory or at their required minutes.
+    pub fn ready_to_claim (&self) -> Vec<String> {
+        self.drops.iter()
+            .filter(|(_, progress)| !progress.claimed && !progress.queued && (progress.claimable || progress.is_complete()))
+            .map(|(drop_id, _)| drop_id.clone())
+            .collect()
+    }
+
+    pub fn mark_claimed (&mut self, drop_id: &str) {
+        if let Some(progress) = self.drops.get_mut(drop_id) {
+            progress.claimed = true;
+            progress.claimable = false;
+        }
+    }
+
+    pub fn mark_queued (&mut self, drop_id: &str) {
+        if let Some(progress) = self.drops.get_mut(drop_id) {
+            progress.queued = true;
+        }
+    }
+}
+
+/// Notices channels that accept `send_watch` but never credit any minutes.
+pub struct StallDetector {
+    window: Duration,
+    channel_id: String,
+    drop_id: String,
+    minutes: u64,
+    since: DateTime<Utc>
+}
+
+impl StallDetector {
+    pub fn new (window: Duration) -> Self {
+        StallDetector { window, channel_id: String::new(), drop_id: String::new(), minutes: 0, since: Utc::now() }
+    }
+
+    /// Starts over, e.g. after a resume where the wall clock jumped.
+    pub fn reset (&mut self) {
+        self.channel_id.clear();
+    }
+
+    /// Returns true once the minutes of the watched drop stayed flat on this channel for the whole window.
+    pub fn observe (&mut self, channel_id: &str, drop_id: &str, minutes: u64) -> bool {
+        if self.channel_id != channel_id || self.drop_id != drop_id || minutes > self.minutes {
+            self.channel_id = channel_id.to_string();
+            self.drop_id = drop_id.to_string();
+            self.minutes = minutes;
+            self.since = Utc::now();
+            return false;
+        }
+        (Utc::now() - self.since).to_std().unwrap_or_default() >= self.window
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use twitch_gql_rs::structs::{CurrentDrop, DropCampaignsInProgress, GetInventory, Inventory, InventorySelf, InventoryTimeBasedDrops};
+
+    use super::{ProgressTracker, StallDetector};
+
+    fn current (drop_id: &str, minutes: u64, required: u64) -> CurrentDrop {
+        CurrentDrop { dropID: drop_id.to_string(), currentMinutesWatched: minutes, requiredMinutesWatched: required, ..Default::default() }
+    }
+
+    fn inventory (drop_id: &str, minutes: u64, claimed: bool, instance_id: Option<&str>) -> GetInventory {
+        let time_based = InventoryTimeBasedDrops {
+            id: drop_id.to_string(),
+            requiredMinutesWatched: 60,
+            self_drop: InventorySelf { currentMinutesWatched: minutes, isClaimed: claimed, dropInstanceID: instance_id.map(str::to_string), ..Default::default() },
+            ..Default::default()
+        };
+        let in_progress = DropCampaignsInProgress { timeBasedDrops: vec![time_based], ..Default::default() };
+        GetInventory { inventory: Inventory { dropCampaignsInProgress: Some(vec![in_progress]) }, ..Default::default() }
+    }
+
+    #[test]
+    fn reports_drop_switches () {
+        let mut tracker = ProgressTracker::default();
+        assert!(!tracker.observe_current(&current("a", 10, 60)));
+        assert!(!tracker.observe_current(&current("a", 11, 60)));
+        assert!(tracker.observe_current(&current("b", 0, 30)));
+        assert_eq!(tracker.current().map(|(drop_id, _)| drop_id), Some("b"));
+    }
+
+    #[test]
+    fn minutes_never_go_backwards () {
+        let mut tracker = ProgressTracker::default();
+        tracker.observe_current(&current("a", 40, 60));
+        tracker.observe_current(&current("a", 35, 60));
+        assert_eq!(tracker.current().map(|(_, progress)| progress.current_minutes), Some(40));
+    }
+
+    #[test]
+    fn complete_drop_is_claimed_once () {
+        let mut tracker = ProgressTracker::default();
+        tracker.observe_current(&current("a", 60, 60));
+        assert!(tracker.awaiting_confirmation());
+        assert_eq!(tracker.ready_to_claim(), ["a"]);
+        tracker.mark_queued("a");
+        assert!(!tracker.awaiting_confirmation());
+        assert!(tracker.ready_to_claim().is_empty());
+    }
+
+    #[test]
+    fn inventory_decides_claimable_and_claimed () {
+        let mut tracker = ProgressTracker::default();
+        tracker.observe_inventory(&inventory("a", 20, false, None));
+        assert!(tracker.ready_to_claim().is_empty());
+        tracker.observe_inventory(&inventory("a", 60, false, Some("instance")));
+        assert_eq!(tracker.ready_to_claim(), ["a"]);
+        assert!(!tracker.awaiting_confirmation());
+        tracker.observe_inventory(&inventory("a", 60, true, Some("instance")));
+        assert!(tracker.ready_to_claim().is_empty());
+    }
+
+    #[test]
+    fn queued_drop_is_claimed_again_once_claimable () {
+        let mut tracker = ProgressTracker::default();
+        tracker.observe_current(&current("a", 60, 60));
+        tracker.mark_queued("a");
+        tracker.observe_inventory(&inventory("a", 60, false, None));
+        assert!(tracker.ready_to_claim().is_empty());
+        // The instance id shows up after the claimer gave up waiting for it
+        tracker.observe_inventory(&inventory("a", 60, false, Some("instance")));
+        assert_eq!(tracker.ready_to_claim(), ["a"]);
+        tracker.mark_queued("a");
+        tracker.observe_inventory(&inventory("a", 60, false, Some("instance")));
+        assert!(tracker.ready_to_claim().is_empty());
+    }
+
+    #[test]
+    fn stall_needs_flat_minutes_for_the_whole_window () {
+        let mut instant = StallDetector::new(Duration::ZERO);
+        assert!(!instant.observe("channel", "a", 10));
+        assert!(instant.observe("channel", "a", 10));
+        // Progress, another drop or another channel start the window over
+        assert!(!instant.observe("channel", "a", 11));
+        assert!(!instant.observe("channel", "b", 11));
+        assert!(!instant.observe("other", "b", 11));
+        instant.reset();
+        assert!(!instant.observe("other", "b", 11));
+
+        let mut slow = StallDetector::new(Duration::from_secs(3600));
+        assert!(!slow.observe("channel", "a", 10));
+        assert!(!slow.observe("channel", "a", 10));
+    }
+}
@@ -0,0 +1,118 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{fs, time::sleep};
+
+use crate::r#static::{Channel, REPUTATION};
+
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+/// Longest gap between two polls that still counts as watching; anything longer was a pause
+const MAX_SAMPLE_GAP: Duration = Duration::from_secs(120);
+/// Minutes a channel has to be watched before its credit ratio means anything
+const MIN_SAMPLE_MINUTES: f64 = 10.0;
+pub const NEUTRAL_SCORE: u32 = 50;
+const STALL_PENALTY: u32 = 10;
+const OFFLINE_PENALTY: u32 = 2;
+const MAX_PENALTY: u32 = 50;
+
+/// What we learned about a channel across runs.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ChannelStats {
+    pub login: String,
+    pub minutes_watched: f64,
+    pub minutes_credited: u64,
+    pub stalls: u64,
+    pub offline: u64
+}
+
+impl ChannelStats {
+    /// 0..=100: the share of watched minutes that were credited.
+    /// Channels without enough history score neutral.
+    pub fn credit_score (&self) -> u32 {
+        if self.minutes_watched < MIN_SAMPLE_MINUTES {
+            NEUTRAL_SCORE
+        } else {
+            ((self.minutes_credited as f64 / self.minutes_watched).min(1.0) * 100.0) as u32
+        }
+    }
+
+    /// 0..=50: deducted for stalls and offline interruptions.
+    pub fn penalty (&self) -> u32 {
+        (self.stalls as u32).saturating_mul(STALL_PENALTY).saturating_add((self.offline as u32).saturating_mul(OFFLINE_PENALTY)).min(MAX_PENALTY)
+    }
+}
+
+pub async fn load_reputation (path: &Path) {
+    let Ok(reputation_str) = fs::read_to_string(path).await else {
+        return;
+    };
+    match serde_json::from_str::<HashMap<String, ChannelStats>>(&reputation_str) {
+        Ok(reputation) => *REPUTATION.lock().await = reputation,
+        Err(e) => tracing::error!("Failed to parse {}: {e}", path.display()),
+    }
+}
+
+/// Writes the reputation store to `path` every minute.
+pub async fn reputation_saver (path: PathBuf) {
+    tokio::spawn(async move {
+        loop {
+            sleep(SAVE_INTERVAL).await;
+            let reputation_str = serde_json::to_string_pretty(&*REPUTATION.lock().await).unwrap();
+            if let Err(e) = fs::write(&path, reputation_str).await {
+                tracing::error!("Failed to save {}: {e}", path.display());
+            }
+        }
+    });
+}
+
+async fn update (channel: &Channel, f: impl FnOnce(&mut ChannelStats)) {
+    let mut reputation = REPUTATION.lock().await;
+    let stats = reputation.entry(channel.channel_id.clone()).or_default();
+    stats.login = channel.channel_login.clone();
+    f(stats);
+}
+
+pub async fn record_stall (channel: &Channel) {
+    update(channel, |stats| stats.stalls += 1).await;
+}
+
+pub async fn record_offline (channel: &Channel) {
+    update(channel, |stats| stats.offline += 1).await;
+}
+
+/// Turns consecutive progress polls into watched and credited minutes for the watched channel.
+pub struct Sampler {
+    channel_id: String,
+    drop_id: String,
+    minutes: u64,
+    at: DateTime<Utc>
+}
+
+impl Sampler {
+    pub fn new () -> Self {
+        Sampler { channel_id: String::new(), drop_id: String::new(), minutes: 0, at: Utc::now() }
+    }
+
+    pub fn reset (&mut self) {
+        self.channel_id.clear();
+    }
+
+    pub async fn sample (&mut self, channel: &Channel, drop_id: &str, minutes: u64) {
+        let now = Utc::now();
+        if self.channel_id == channel.channel_id && self.drop_id == drop_id {
+            let elapsed = (now - self.at).to_std().unwrap_or_default();
+            if elapsed <= MAX_SAMPLE_GAP {
+                let credited = minutes.saturating_sub(self.minutes);
+                update(channel, |stats| {
+                    stats.minutes_watched += elapsed.as_secs_f64() / 60.0;
+                    stats.minutes_credited += credited;
+                }).await;
+            }
+        }
+        self.channel_id = channel.channel_id.clone();
+        self.drop_id = drop_id.to_string();
+        self.minutes = minutes;
+        self.at = now;
+    }
+}
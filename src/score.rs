@@ -0,0 +1,139 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::r#static::Channel;
+
+/// Viewer count at which the viewers component maxes out
+const VIEWERS_CAP: f64 = 10_000.0;
+/// Observed uptime at which the uptime component maxes out
+const UPTIME_CAP_HOURS: f64 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelSource {
+    /// Listed in the campaign's channel allowlist
+    Allowlist,
+    /// Found in the game directory of a campaign without an allowlist
+    Directory
+}
+
+/// Everything known about a candidate channel when it is ranked.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub channel: Channel,
+    pub source: ChannelSource,
+    /// Number of selected campaigns the channel progresses
+    pub campaigns: usize,
+    /// Position of the soonest ending campaign the channel progresses among the campaigns
+    /// with drops left, 0 being the next one to end
+    pub deadline_rank: Option<usize>,
+    pub viewers: Option<u64>,
+    pub tags: Vec<String>,
+    /// When the stream was first seen live in this run
+    pub live_since: Option<DateTime<Utc>>,
+    /// Share of watched minutes the channel credited in the past, 0..=100
+    pub reputation: u32,
+    /// Deductions for past stalls and offline interruptions, 0..=50
+    pub penalty: u32
+}
+
+/// Weights of the default scorer, set under `[weights]` in the config.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ScoreWeights {
+    pub allowlist: f64,
+    pub directory: f64,
+    pub viewers: f64,
+    pub language: f64,
+    pub uptime: f64,
+    pub reputation: f64,
+    pub campaigns: f64,
+    /// Given in full to channels of the campaign that ends first, a half for the second, a third for the third, ...
+    pub deadline: f64
+}
+
+impl Default for ScoreWeights {
+    fn default () -> Self {
+        ScoreWeights { allowlist: 3.0, directory: 1.0, viewers: 0.5, language: 1.0, uptime: 0.5, reputation: 2.0, campaigns: 1.0, deadline: 4.0 }
+    }
+}
+
+/// Ranks candidate channels. The score is the sum of the named components,
+/// so the same breakdown can be shown to explain a pick.
+pub trait ChannelScorer: Send + Sync {
+    fn components (&self, candidate: &Candidate) -> Vec<(&'static str, f64)>;
+
+    fn score (&self, candidate: &Candidate) -> ScoredChannel {
+        let components = self.components(candidate);
+        let score = components.iter().map(|(_, value)| value).sum();
+        ScoredChannel { channel: candidate.channel.clone(), score, components }
+    }
+}
+
+pub struct DefaultScorer {
+    weights: ScoreWeights,
+    /// Preferred stream languages, matched against the stream tags
+    languages: Vec<String>
+}
+
+impl DefaultScorer {
+    pub fn new (weights: ScoreWeights, languages: Vec<String>) -> Self {
+        DefaultScorer { weights, languages }
+    }
+}
+
+impl ChannelScorer for DefaultScorer {
+    fn components (&self, candidate: &Candidate) -> Vec<(&'static str, f64)> {
+        let source = match candidate.source {
+            ChannelSource::Allowlist => ("allowlist", self.weights.allowlist),
+            ChannelSource::Directory => ("directory", self.weights.directory),
+        };
+        // Logarithmic, so a huge stream does not drown out everything else
+        let viewers = candidate.viewers.map_or(0.0, |viewers| ((viewers as f64).ln_1p() / VIEWERS_CAP.ln_1p()).min(1.0));
+        let language = candidate.tags.iter().any(|tag| self.languages.iter().any(|language| tag.eq_ignore_ascii_case(language)));
+        let deadline = candidate.deadline_rank.map_or(0.0, |rank| 1.0 / (rank as f64 + 1.0));
+        let uptime = candidate.live_since.map_or(0.0, |since| ((Utc::now() - since).num_minutes() as f64 / 60.0 / UPTIME_CAP_HOURS).clamp(0.0, 1.0));
+        vec![
+            source,
+            ("campaigns", self.weights.campaigns * candidate.campaigns as f64),
+            ("deadline", self.weights.deadline * deadline),
+            ("viewers", self.weights.viewers * viewers),
+            ("language", if language { self.weights.language } else { 0.0 }),
+            ("uptime", self.weights.uptime * uptime),
+            ("reputation", self.weights.reputation * candidate.reputation as f64 / 100.0),
+            ("penalties", -self.weights.reputation * candidate.penalty as f64 / 100.0),
+        ]
+    }
+}
+
+/// A ranked channel together with the components its score is made of.
+#[derive(Debug, Clone)]
+pub struct ScoredChannel {
+    pub channel: Channel,
+    pub score: f64,
+    pub components: Vec<(&'static str, f64)>
+}
+
+impl std::fmt::Display for ScoredChannel {
+    fn fmt (&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let components: Vec<String> = self.components.iter().filter(|(_, value)| *value != 0.0).map(|(name, value)| format!("{name} {value:+.2}")).collect();
+        write!(f, "{} {:.2} ({})", self.channel.channel_login, self.score, components.join(", "))
+    }
+}
+
+/// Why the watched channel was picked: its score breakdown and the best channels behind it.
+#[derive(Debug, Clone)]
+pub struct Selection {
+    pub watched: ScoredChannel,
+    pub runners_up: Vec<ScoredChannel>
+}
+
+impl std::fmt::Display for Selection {
+    fn fmt (&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "picked {}", self.watched)?;
+        if !self.runners_up.is_empty() {
+            let runners_up: Vec<String> = self.runners_up.iter().map(ToString::to_string).collect();
+            write!(f, "; runners-up: {}", runners_up.join("; "))?;
+        }
+        Ok(())
+    }
+}
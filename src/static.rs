@@ -1,24 +1,83 @@
 use std::{collections::{HashMap, HashSet}, sync::Arc, time::Duration};
 
+use chrono::{DateTime, Utc};
 use once_cell::sync::Lazy;
-use tokio::{sync::Mutex, time::sleep};
+use tokio::{sync::{Mutex, Notify}, time::sleep};
 use twitch_gql_rs::structs::{Channels, GameDirectory};
 
+use crate::{reputation::ChannelStats, score::Selection};
+
 #[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Channel {
     pub channel_id: String,
     pub channel_login: String
 }
 
+/// Live stream details collected while discovering channels, used to score them
+#[derive(Debug, Clone)]
+pub struct StreamStats {
+    pub viewers: u64,
+    pub tags: Vec<String>,
+    pub live_since: DateTime<Utc>
+}
+
 const MAX_ATTEMPTS: u32 = 3;
 
+pub type CampaignChannels<T> = Arc<Mutex<HashMap<String, HashSet<T>>>>;
+
+pub type ChannelCampaigns = Arc<Mutex<HashMap<String, HashSet<String>>>>;
+
+pub type Cooldowns = Arc<Mutex<HashMap<String, DateTime<Utc>>>>;
+
 pub static DROP_CASH: Lazy<Arc<Mutex<HashSet<String>>>> = Lazy::new(|| Arc::new(Mutex::new(HashSet::new())));
 
+/// Reward (benefit) ids of the drops the miner claimed, kept next to the drop cache
+pub static OWNED_BENEFITS: Lazy<Arc<Mutex<HashSet<String>>>> = Lazy::new(|| Arc::new(Mutex::new(HashSet::new())));
+
+/// Drop ids a claim is running for right now
+pub static CLAIMING: Lazy<Arc<Mutex<HashSet<String>>>> = Lazy::new(|| Arc::new(Mutex::new(HashSet::new())));
+
+pub static GAME_SLUGS: Lazy<Arc<Mutex<HashMap<String, String>>>> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+/// Channels that stopped crediting progress, by channel id, with the end of their cooldown
+pub static SUSPECT_CHANNELS: Lazy<Cooldowns> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+/// Per-channel statistics by channel id, persisted in `reputation.json`
+pub static REPUTATION: Lazy<Arc<Mutex<HashMap<String, ChannelStats>>>> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+pub static STREAM_STATS: Lazy<Arc<Mutex<HashMap<String, StreamStats>>>> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+/// The channel each watch slot picked last and why, by slot
+pub static SELECTION: Lazy<Arc<Mutex<HashMap<usize, Selection>>>> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+/// The channel each watch slot is watching, so two slots never pick the same one
+pub static SLOT_CHANNELS: Lazy<Arc<Mutex<HashMap<usize, Channel>>>> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+/// Selected campaigns each discovered channel progresses, by channel id
+pub static CHANNEL_CAMPAIGNS: Lazy<ChannelCampaigns> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+/// Ids of the selected campaigns that still have drops left to earn, `None` until they are known
+pub static INCOMPLETE_CAMPAIGNS: Lazy<Arc<Mutex<Option<HashSet<String>>>>> = Lazy::new(|| Arc::new(Mutex::new(None)));
+
+/// When the ranking last had a channel to watch, `None` until it had one
+pub static LAST_CANDIDATE: Lazy<Arc<Mutex<Option<DateTime<Utc>>>>> = Lazy::new(|| Arc::new(Mutex::new(None)));
+
+/// Wakes the ranking up before its next round, e.g. when an allowlisted channel goes live
+pub static RERANK: Lazy<Notify> = Lazy::new(Notify::new);
+
 pub static CHANNEL_IDS: Lazy<Arc<Mutex<HashSet<Channel>>>> = Lazy::new(|| Arc::new(Mutex::new(HashSet::new())));
 
-pub static DEFAULT_CHANNELS: Lazy<Arc<Mutex<HashMap<String, HashSet<GameDirectory>>>>> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+pub static DEFAULT_CHANNELS: Lazy<CampaignChannels<GameDirectory>> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
 
-pub static ALLOW_CHANNELS: Lazy<Arc<Mutex<HashMap<String, HashSet<Channels>>>>> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+pub static ALLOW_CHANNELS: Lazy<CampaignChannels<Channels>> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+/// Ids of the suspect channels that are still cooling down.
+pub async fn suspect_channels () -> HashSet<String> {
+    let mut suspects = SUSPECT_CHANNELS.lock().await;
+    let now = Utc::now();
+    suspects.retain(|_, until| *until > now);
+    suspects.keys().cloned().collect()
+}
 
 #[macro_export]
 macro_rules! retry {
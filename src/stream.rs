@@ -1,247 +1,435 @@
-use std::{collections::{BinaryHeap, HashSet}, error::Error, sync::Arc, time::Duration};
+use std::{collections::{BinaryHeap, HashMap, HashSet}, error::Error, sync::Arc, time::Duration};
 
-use tokio::sync::{Mutex, Notify, broadcast::{self, Sender}, watch::Receiver};
+use tokio::sync::{broadcast::Sender, mpsc::UnboundedReceiver, watch::Receiver};
 
+use chrono::{DateTime, Utc};
 use futures_util::{SinkExt, StreamExt};
 use serde_json::{Value, json};
 use tokio::time::sleep;
 use tokio_tungstenite::{connect_async, tungstenite::Message};
-use tracing::debug;
-use twitch_gql_rs::{TwitchClient, structs::{Channels, DropCampaigns, GameDirectory}};
+use tracing::{debug, info};
+use twitch_gql_rs::{TwitchClient, structs::{AvailableDrops, DropCampaigns, GameDirectory, StreamGame}};
 
-use crate::{retry, r#static::{ALLOW_CHANNELS, CHANNEL_IDS, Channel, DEFAULT_CHANNELS, retry_backup}};
+use crate::{campaign::parse_time, reputation::NEUTRAL_SCORE, retry, score::{Candidate, ChannelScorer, ChannelSource, ScoredChannel, Selection}, r#static::{ALLOW_CHANNELS, CHANNEL_CAMPAIGNS, CHANNEL_IDS, Channel, DEFAULT_CHANNELS, INCOMPLETE_CAMPAIGNS, LAST_CANDIDATE, RERANK, REPUTATION, SELECTION, SLOT_CHANNELS, STREAM_STATS, SUSPECT_CHANNELS, StreamStats, retry_backup, suspect_channels}};
 
 const UPDATE_TIME: u64 = 15;
+/// Channels listed behind the picked one when explaining a selection
+const RUNNERS_UP: usize = 3;
+/// How long a channel a slot had to leave (offline, no drop progress) is skipped
+const SKIP_COOLDOWN: Duration = Duration::from_secs(10 * 60);
+/// Pubsub topics one socket may listen to
 const MAX_TOPICS: usize = 50;
-const WS_URL: &'static str = "wss://pubsub-edge.twitch.tv/v1";
+/// Topics kept free for allowlisted channels that are offline, to hear when they go live
+const PREEMPT_TOPICS: usize = 10;
+const MAX_CANDIDATES: usize = MAX_TOPICS - PREEMPT_TOPICS;
+const PLAYBACK_TOPIC: &str = "video-playback-by-id";
+const SETTINGS_TOPIC: &str = "broadcast-settings-update";
+const WS_URL: &str = "wss://pubsub-edge.twitch.tv/v1";
+
+/// Drops channel lists of campaigns that are gone and loads the allowlist or game directory of new ones.
+async fn sync_campaigns (client: &TwitchClient, campaigns: &[DropCampaigns]) {
+    let ids: HashSet<&String> = campaigns.iter().map(|campaign| &campaign.id).collect();
+
+    let mut allow_channels = ALLOW_CHANNELS.lock().await;
+    let mut default_channels = DEFAULT_CHANNELS.lock().await;
+    allow_channels.retain(|id, _| ids.contains(id));
+    default_channels.retain(|id, _| ids.contains(id));
+
+    let mut channel_ids = CHANNEL_IDS.lock().await;
+    channel_ids.retain(|channel| {
+        allow_channels.values().any(|allow| allow.iter().any(|c| c.id == channel.channel_id))
+            || default_channels.values().any(|default| default.iter().any(|c| c.broadcaster.id == channel.channel_id))
+    });
+    drop(channel_ids);
+
+    let mut channel_campaigns = CHANNEL_CAMPAIGNS.lock().await;
+    for covered in channel_campaigns.values_mut() {
+        covered.retain(|id| ids.contains(id));
+    }
+    drop(channel_campaigns);
 
-pub async fn filter_streams (client: Arc<TwitchClient>, campaigns: Arc<Vec<DropCampaigns>>) {
-    let mut count = 0;
-    let mut video_vec = HashSet::new();
-    for campaign in campaigns.iter() {
+    let new_campaigns: Vec<&DropCampaigns> = campaigns.iter().filter(|campaign| !allow_channels.contains_key(&campaign.id) && !default_channels.contains_key(&campaign.id)).collect();
+    drop(allow_channels);
+    drop(default_channels);
+
+    for campaign in new_campaigns {
         let campaign_details = retry!(client.get_campaign_details(&campaign.id));
         if let Some(allow) = campaign_details.allow.channels {
-            let mut allow_channels = ALLOW_CHANNELS.lock().await;
-            let allow: HashSet<Channels> = allow.into_iter().collect();
-            allow_channels.insert(campaign.id.to_string(), allow.clone());
-            drop(allow_channels);
-            for channel in allow {
-                let stream_info = retry!(client.get_stream_info(&channel.name));
-                if stream_info.stream.is_some() {
-                    if count >= MAX_TOPICS {
-                        break;
-                    }
-                    let avaiable_drops = retry!(client.get_available_drops_for_channel(&channel.id));
-                    if avaiable_drops.viewerDropCampaigns.is_some() {
-                        video_vec.insert(Channel { channel_id: channel.id, channel_login: channel.name });
-                        count += 1
-                    }
-                }
-            }
+            ALLOW_CHANNELS.lock().await.insert(campaign.id.to_string(), allow.into_iter().collect());
         } else {
             let game_directory = retry!(client.get_game_directory(&campaign_details.game.slug, 30, true));
-            let mut all_default = DEFAULT_CHANNELS.lock().await;
-            let game_directory: HashSet<GameDirectory> = game_directory.into_iter().collect();
-            all_default.insert(campaign.id.to_string(), game_directory.clone());
-            drop(all_default);
-            for channel in game_directory {
-                let stream_info = retry!(client.get_stream_info(&channel.broadcaster.login));
-                if count >= MAX_TOPICS {
-                    break;
-                }
-                if stream_info.stream.is_some() {
-                    let available_drops = retry!(client.get_available_drops_for_channel(&channel.broadcaster.id));
-                    if available_drops.viewerDropCampaigns.is_some() {
-                        video_vec.insert(Channel { channel_id: channel.broadcaster.id, channel_login: channel.broadcaster.login });
-                        count += 1
-                    }
-                }
-            }
+            DEFAULT_CHANNELS.lock().await.insert(campaign.id.to_string(), game_directory.into_iter().collect());
         }
     }
-    let mut lock = CHANNEL_IDS.lock().await;
-    *lock = video_vec;
-    drop(lock);
-    debug!("Drop lock video");
-    spawn_ws(client.access_token.clone().unwrap()).await;
+}
+
+/// Keeps the viewers and tags of a live channel and when it was first seen live.
+async fn record_stream (channel_id: &str, viewers: u64, tags: Option<Vec<String>>) {
+    let mut stream_stats = STREAM_STATS.lock().await;
+    let stats = stream_stats.entry(channel_id.to_string()).or_insert_with(|| StreamStats { viewers, tags: Vec::new(), live_since: Utc::now() });
+    stats.viewers = viewers;
+    if let Some(tags) = tags {
+        stats.tags = tags;
+    }
+}
+
+/// Remembers which of the selected campaigns the channel progresses. Returns false if it progresses none.
+async fn record_coverage (channel_id: &str, available_drops: &AvailableDrops, campaigns: &[DropCampaigns]) -> bool {
+    let covered: HashSet<String> = available_drops.viewerDropCampaigns.iter().flatten()
+        .filter(|available| campaigns.iter().any(|campaign| campaign.id == available.id))
+        .map(|available| available.id.clone())
+        .collect();
+    let progresses = !covered.is_empty();
+    CHANNEL_CAMPAIGNS.lock().await.insert(channel_id.to_string(), covered);
+    progresses
+}
+
+/// Game ids of the selected campaigns the channel progresses, or of all of them if that is not known yet.
+async fn campaign_games (channel_id: &str, campaigns: &[DropCampaigns]) -> HashSet<String> {
+    let covered = CHANNEL_CAMPAIGNS.lock().await.get(channel_id).cloned().unwrap_or_default();
+    campaigns.iter()
+        .filter(|campaign| covered.is_empty() || covered.contains(&campaign.id))
+        .map(|campaign| campaign.game.id.clone())
+        .collect()
+}
+
+/// Why the watched channel stopped earning drops for the selected campaigns, if it did: it moved
+/// to a game none of its campaigns belong to, or it has no drops for them anymore.
+pub async fn lost_eligibility (client: &TwitchClient, channel: &Channel, game: &StreamGame, campaigns: &[DropCampaigns]) -> Option<String> {
+    if campaigns.is_empty() {
+        return None;
+    }
+    if !campaign_games(&channel.channel_id, campaigns).await.contains(&game.id) {
+        return Some(format!("switched category to {}", game.displayName));
+    }
+    match client.get_available_drops_for_channel(&channel.channel_id).await {
+        Ok(available_drops) => (!record_coverage(&channel.channel_id, &available_drops, campaigns).await).then(|| "no longer has drops for the selected campaigns".to_string()),
+        Err(e) => {
+            debug!("Failed to check the drops of {}: {e}", channel.channel_login);
+            None
+        }
+    }
+}
+
+/// Keeps the channel out of the ranking and away from every slot until `cooldown` is over.
+async fn skip_channel (channel_id: &str, cooldown: Duration) {
+    let mut suspects = SUSPECT_CHANNELS.lock().await;
+    let until = Utc::now() + cooldown;
+    let entry = suspects.entry(channel_id.to_string()).or_insert(until);
+    *entry = (*entry).max(until);
+}
+
+/// Stops considering a channel that no longer earns drops: it leaves the candidates and is skipped
+/// for a while, so the slot watching it picks another one with the next ranking.
+pub async fn drop_channel (channel_id: &str) {
+    CHANNEL_IDS.lock().await.retain(|channel| channel.channel_id != channel_id);
+    skip_channel(channel_id, SKIP_COOLDOWN).await;
+    RERANK.notify_one();
+}
+
+/// After a resume the candidates may have gone offline without pubsub telling us: keeps only the live ones.
+async fn prune_offline (client: &TwitchClient) {
+    let channel_ids = CHANNEL_IDS.lock().await.clone();
+    for channel in channel_ids {
+        match client.get_stream_info(&channel.channel_login).await {
+            Ok(stream_info) if stream_info.stream.is_none() => {
+                debug!("{} went offline during the suspend", channel.channel_login);
+                demote(&channel.channel_id).await;
+            },
+            Ok(_) => {},
+            Err(e) => debug!("Failed to re-check {}: {e}", channel.channel_login),
+        }
+    }
+}
+
+pub async fn filter_streams (client: Arc<TwitchClient>, mut rx_campaigns: Receiver<Vec<DropCampaigns>>, mut rx_resume: Receiver<u64>) {
+    spawn_ws(client.clone(), rx_campaigns.clone(), rx_resume.clone()).await;
 
     tokio::spawn(async move {
         loop {
+            let campaigns = rx_campaigns.borrow_and_update().clone();
+            sync_campaigns(&client, &campaigns).await;
+
             let lock = CHANNEL_IDS.lock().await;
             let count = lock.len();
             drop(lock);
-            if count < MAX_TOPICS {
+            if count < MAX_CANDIDATES {
                 let mut to_add = HashSet::new();
                 for campaign in campaigns.iter() {
-                    let allow_channels = ALLOW_CHANNELS.lock().await;
-                    if let Some(channels) = allow_channels.get(&campaign.id) {
-                        for channel in channels {
-                            if to_add.len() + count  >= MAX_TOPICS {
+                    // Cloned so pubsub and promotion are not blocked while the channels are checked
+                    let allowed = ALLOW_CHANNELS.lock().await.get(&campaign.id).cloned();
+                    if let Some(channels) = allowed {
+                        for channel in &channels {
+                            if to_add.len() + count  >= MAX_CANDIDATES {
                                 break;
                             }
                             let stream_info = retry!(client.get_stream_info(&channel.name));
-                            if stream_info.stream.is_some() {
+                            if let Some(stream) = &stream_info.stream {
+                                record_stream(&channel.id, stream.viewersCount, Some(stream.tags.clone())).await;
                                 let available_drops = retry!(client.get_available_drops_for_channel(&channel.id));
-                                if available_drops.viewerDropCampaigns.is_some() {
+                                if record_coverage(&channel.id, &available_drops, &campaigns).await {
                                     to_add.insert(Channel { channel_id: channel.id.clone(), channel_login: channel.name.clone() });
                                 }
                             }
                         }
                     } else {
-                        let mut default_channels = DEFAULT_CHANNELS.lock().await;
                         let slug = retry!(client.get_slug(&campaign.game.displayName));
                         let game_directory = retry!(client.get_game_directory(&slug, 30, true));
                         let game_directory: HashSet<GameDirectory> = game_directory.into_iter().collect();
-                        default_channels.insert(campaign.id.clone(), game_directory.clone());
+                        DEFAULT_CHANNELS.lock().await.insert(campaign.id.clone(), game_directory.clone());
                         for channel in &game_directory {
+                            record_stream(&channel.broadcaster.id, channel.viewersCount, None).await;
                             let available_drops = retry!(client.get_available_drops_for_channel(&channel.broadcaster.id));
-                            if available_drops.viewerDropCampaigns.is_some() {
+                            if record_coverage(&channel.broadcaster.id, &available_drops, &campaigns).await {
                                 to_add.insert(Channel { channel_id: channel.broadcaster.id.clone(), channel_login: channel.broadcaster.login.clone() });
-                                if to_add.len() + count  >= MAX_TOPICS {
+                                if to_add.len() + count  >= MAX_CANDIDATES {
                                     break;
                                 }
                             }
                         }
-                        drop(default_channels);
                     }
-                    if to_add.len() + count >= MAX_TOPICS {
+                    if to_add.len() + count >= MAX_CANDIDATES {
                         break;
                     }
 
                 }
 
                 let mut lock = CHANNEL_IDS.lock().await;
-                let mut cur = lock.len();
-                for channel in to_add {
-                    if cur >= MAX_TOPICS { 
-                        break 
-                    };
-                    lock.insert(channel);
-                    cur += 1;
-                }
+                let free = MAX_CANDIDATES.saturating_sub(lock.len());
+                lock.extend(to_add.into_iter().take(free));
                 drop(lock);
             }
             debug!("Drop ids");
-            sleep(Duration::from_secs(UPDATE_TIME)).await
+            tokio::select! {
+                _ = rx_campaigns.changed() => {},
+                _ = sleep(Duration::from_secs(UPDATE_TIME)) => {},
+                Ok(()) = rx_resume.changed() => prune_offline(&client).await
+            }
         }
     });
 }
 
 //ws_logick
-async fn spawn_ws (auth_token: String) {
+/// Pubsub topics to listen to: the playback of every candidate, the broadcast settings of the watched
+/// channels, to hear about category changes, and the playback of allowlisted channels that are not
+/// candidates, to hear about them going live.
+async fn wanted_topics () -> HashSet<String> {
+    let channel_ids = CHANNEL_IDS.lock().await.clone();
+    let candidate_ids: HashSet<&String> = channel_ids.iter().map(|channel| &channel.channel_id).collect();
+    // A socket only takes `MAX_TOPICS` topics, whatever kind they are
+    let mut topics: HashSet<String> = SLOT_CHANNELS.lock().await.values().map(|channel| format!("{SETTINGS_TOPIC}.{}", channel.channel_id)).collect();
+    let free = MAX_TOPICS.saturating_sub(topics.len());
+    topics.extend(candidate_ids.iter().take(free).map(|channel_id| format!("{PLAYBACK_TOPIC}.{channel_id}")));
+    let allow_channels = ALLOW_CHANNELS.lock().await;
+    let mut offline: Vec<&String> = allow_channels.values().flatten().map(|channel| &channel.id).filter(|id| !candidate_ids.contains(id)).collect();
+    offline.sort();
+    offline.dedup();
+    let free = MAX_TOPICS.saturating_sub(topics.len()).min(PREEMPT_TOPICS);
+    topics.extend(offline.into_iter().take(free).map(|channel_id| format!("{PLAYBACK_TOPIC}.{channel_id}")));
+    topics
+}
+
+async fn send_topics<S> (write: &mut S, kind: &str, topics: &HashSet<String>, auth_token: &str) where S: SinkExt<Message> + Unpin, S::Error: std::fmt::Display {
+    let payload = json!({
+        "type": kind,
+        "data": {
+            "topics": topics,
+            "auth_token": auth_token
+        }
+    });
+    let payload = serde_json::to_string(&payload).unwrap();
+    write.send(Message::Text(payload.into())).await.unwrap_or_else(|e| tracing::error!("Failed to send payload to WebSocket: {e}"));
+}
+
+/// An allowlisted channel that is not a candidate went live: if it has drops for the selected campaigns,
+/// make it a candidate and rank it right away. Whether the slots actually switch to it is up to their switch policy.
+async fn promote (client: &TwitchClient, channel_id: &str, campaigns: &[DropCampaigns]) {
+    let allow_channels = ALLOW_CHANNELS.lock().await;
+    let Some(channel) = allow_channels.values().flatten().find(|channel| channel.id == channel_id) else {
+        return;
+    };
+    let channel = Channel { channel_id: channel.id.clone(), channel_login: channel.name.clone() };
+    let allowlisted: HashSet<String> = allow_channels.values().flatten().map(|channel| channel.id.clone()).collect();
+    drop(allow_channels);
+    if CHANNEL_IDS.lock().await.contains(&channel) {
+        return;
+    }
+
+    match client.get_stream_info(&channel.channel_login).await {
+        Ok(stream_info) => if let Some(stream) = stream_info.stream {
+            record_stream(&channel.channel_id, stream.viewersCount, Some(stream.tags)).await;
+        },
+        Err(e) => debug!("Failed to load the stream of {}: {e}", channel.channel_login),
+    }
+    let available_drops = match client.get_available_drops_for_channel(&channel.channel_id).await {
+        Ok(available_drops) => available_drops,
+        Err(e) => {
+            debug!("Failed to check the drops of {}: {e}", channel.channel_login);
+            return;
+        }
+    };
+    if !record_coverage(&channel.channel_id, &available_drops, campaigns).await {
+        debug!("{} went live without drops for the selected campaigns", channel.channel_login);
+        return;
+    }
+
+    let watched: HashSet<String> = SLOT_CHANNELS.lock().await.values().map(|channel| channel.channel_id.clone()).collect();
+    let viewers: HashMap<String, u64> = STREAM_STATS.lock().await.iter().map(|(id, stats)| (id.clone(), stats.viewers)).collect();
+    let mut channel_ids = CHANNEL_IDS.lock().await;
+    if channel_ids.len() >= MAX_CANDIDATES {
+        // Makes room by evicting the directory channel with the fewest viewers that no slot is watching
+        let evicted = channel_ids.iter()
+            .filter(|candidate| !allowlisted.contains(&candidate.channel_id) && !watched.contains(&candidate.channel_id))
+            .min_by_key(|candidate| viewers.get(&candidate.channel_id).copied().unwrap_or(0))
+            .cloned();
+        let Some(evicted) = evicted else {
+            debug!("No room to promote {}", channel.channel_login);
+            return;
+        };
+        debug!("Evicting {} to promote {}", evicted.channel_login, channel.channel_login);
+        channel_ids.remove(&evicted);
+    }
+    if channel_ids.insert(channel.clone()) {
+        info!("Allowlisted channel {} went live", channel.channel_login);
+        RERANK.notify_one();
+    }
+}
+
+/// The channel went offline: it is no candidate anymore until it is discovered or goes live again.
+async fn demote (channel_id: &str) {
+    CHANNEL_IDS.lock().await.retain(|channel| channel.channel_id != channel_id);
+    STREAM_STATS.lock().await.remove(channel_id);
+}
+
+/// A watched channel changed its broadcast settings: leave it if it moved to a game none of its campaigns belong to.
+async fn settings_changed (channel_id: &str, message_json: &Value, campaigns: &[DropCampaigns]) {
+    // The game id comes as a number
+    let Some(game_id) = message_json.get("game_id").map(|id| id.as_str().map_or_else(|| id.to_string(), str::to_string)) else {
+        return;
+    };
+    if campaigns.is_empty() || campaign_games(channel_id, campaigns).await.contains(&game_id) {
+        return;
+    }
+    let game = message_json.get("game").and_then(Value::as_str).unwrap_or(&game_id);
+    info!("Channel {channel_id} switched category to {game}");
+    drop_channel(channel_id).await;
+}
+
+async fn handle_message (client: &TwitchClient, text: &str, campaigns: &[DropCampaigns]) {
+    let Ok(json) = serde_json::from_str::<Value>(text) else {
+        tracing::error!("Failed to parse WebSocket message: {text}");
+        return;
+    };
+    if let Some(err) = json.get("error") {
+        if err != "" {
+            tracing::error!("{err}")
+        }
+        return;
+    }
+    if json.get("type").and_then(Value::as_str) != Some("MESSAGE") {
+        return;
+    }
+    let data = check_json(&json, "data").unwrap_or_else(|e| {tracing::error!("{e}"); &Value::Null});
+    let message = check_json(data, "message").unwrap_or_else(|e| {tracing::error!("{e}"); &Value::Null}).as_str().unwrap_or_default();
+    let topic = check_json(data, "topic").unwrap_or_else(|e| { tracing::error!("{e}"); &Value::Null }).as_str().unwrap_or_default();
+    let Some((kind, channel_id)) = topic.rsplit_once('.') else {
+        return;
+    };
+    let Ok(message_json) = serde_json::from_str::<Value>(message) else {
+        tracing::error!("Failed to parse pubsub message on {topic}: {message}");
+        return;
+    };
+    if kind == SETTINGS_TOPIC {
+        settings_changed(channel_id, &message_json, campaigns).await;
+        return;
+    }
+    match message_json.get("type").and_then(Value::as_str) {
+        Some("stream-up") => promote(client, channel_id, campaigns).await,
+        Some("stream-down") => demote(channel_id).await,
+        Some("viewcount") if message_json.get("viewers").and_then(Value::as_u64) == Some(0) => demote(channel_id).await,
+        _ => {}
+    }
+}
+
+async fn spawn_ws (client: Arc<TwitchClient>, rx_campaigns: Receiver<Vec<DropCampaigns>>, mut rx_resume: Receiver<u64>) {
+    let auth_token = client.access_token.clone().unwrap();
     tokio::spawn(async move {
         loop {
-            let mut send_channels: HashSet<Channel> = HashSet::new();
+            let mut listening: HashSet<String> = HashSet::new();
             let (ws_stream, _) = retry!(connect_async(WS_URL));
             let (mut write, mut read) = ws_stream.split();
             loop {
-                let mut channel_ids = CHANNEL_IDS.lock().await;
-                let new_channels: Vec<Channel> = channel_ids.iter().filter(|id| !send_channels.contains(*id)).cloned().collect();
-                let delete_channels: Vec<Channel> = send_channels.iter().filter(|id| !channel_ids.contains(&id)).cloned().collect();
-
-                if !new_channels.is_empty() {
-                    let topics: Vec<String> = new_channels.iter().map(|channel| format!("video-playback-by-id.{}", channel.channel_id)).collect();
-                    let payload = json!({
-                        "type": "LISTEN",
-                        "data": {
-                            "topics": topics,
-                            "auth_token": auth_token
-                        }
-                    });
-                    let payload = serde_json::to_string(&payload).unwrap();
-                    let payload = tokio_tungstenite::tungstenite::Message::Text(payload.into());
-                    write.send(payload).await.unwrap_or_else(|e| tracing::error!("Failed to send payload to WebSocket: {e}"));
-                    send_channels.extend(new_channels);
-                }
+                let wanted = wanted_topics().await;
+                let listen: HashSet<String> = wanted.difference(&listening).cloned().collect();
+                let unlisten: HashSet<String> = listening.difference(&wanted).cloned().collect();
 
-                if !delete_channels.is_empty() {
-                    let delete_topics: Vec<String> = delete_channels.iter().map(|channel| format!("video-playback-by-id.{}", channel.channel_id)).collect();
-                    let payload = json!({
-                        "type": "UNLISTEN",
-                        "data": {
-                            "topics": delete_topics,
-                            "auth_token": auth_token
-                        }
-                    });
-                    let payload = serde_json::to_string(&payload).unwrap();
-                    let payload = tokio_tungstenite::tungstenite::Message::Text(payload.into());
-                    write.send(payload).await.unwrap_or_else(|e| tracing::error!("Failed to send payload to WebSocket: {e}"));
-                    for delete in delete_channels {
-                        send_channels.remove(&delete);
+                if !listen.is_empty() {
+                    send_topics(&mut write, "LISTEN", &listen, &auth_token).await;
+                }
+                if !unlisten.is_empty() {
+                    send_topics(&mut write, "UNLISTEN", &unlisten, &auth_token).await;
+                }
+                listening = wanted;
+
+                // Wakes up now and then even without messages, so the topics follow the candidates
+                let msg = tokio::select! {
+                    msg = read.next() => msg,
+                    _ = sleep(Duration::from_secs(UPDATE_TIME)) => continue,
+                    // The socket may be half-open after a suspend, start over with a fresh one
+                    Ok(()) = rx_resume.changed() => {
+                        info!("Reconnecting to pubsub after resume");
+                        break;
                     }
                 };
-
-                if let Some(msg) = read.next().await {
-                    match msg {
-                        Ok(Message::Text(text)) => {
-                            if text.contains("\"type\":\"PING\"") {
-                                let pong = Message::Text("{\"type\":\"PONG\"}".into());
-                                write.send(pong).await.unwrap();
-                            }
-                            let json: Value = serde_json::from_str(&text).unwrap();
-                            if let Some(err) = json.get("error") {
-                                if err != "" {
-                                    tracing::error!("{err}")
-                                }
-                            } else {
-                                let data = check_json(&json, "data").unwrap_or_else(|e| {tracing::error!("{e}"); &Value::Null});
-                                let message = check_json(&data, "message").unwrap_or_else(|e| {tracing::error!("{e}"); &Value::Null}).as_str().unwrap_or_default();
-                                let topic = check_json(data, "topic").unwrap_or_else(|e| { tracing::error!("{e}"); &Value::Null }).as_str().unwrap_or_default();
-                                let message_json: Value = serde_json::from_str(&message).unwrap();
-                                if let Some(viewers) = message_json.get("viewers").and_then(|s| s.as_u64()) {
-                                    if viewers == 0 {
-                                        if let Some(id_str) = topic.split('.').last() {
-                                            let channel_id_to_remove = channel_ids.iter().find(|channel| channel.channel_id == id_str).cloned();
-                                            if let Some(to_remove) = channel_id_to_remove {
-                                                channel_ids.remove(&to_remove);
-                                            }
-                                            send_channels.retain(|channel| channel.channel_id != id_str );
-                                        }
-                                    }
-                                } else {
-                                    if let Some(id_str) = topic.split('.').last() {
-                                        let channel_id_to_remove = channel_ids.iter().find(|channel| channel.channel_id == id_str).cloned();
-                                        if let Some(to_remove) = channel_id_to_remove {
-                                            channel_ids.remove(&to_remove);
-                                        }
-                                        send_channels.retain(|channel| channel.channel_id != id_str );
-                                    }
-                                }
-                            }
-
-                        },
-                        Ok(Message::Ping(ping)) => write.send(Message::Pong(ping)).await.unwrap_or_else(|e| tracing::error!("Failed to send PONG to WebSocket: {e}")),
-                        Ok(_) => {},
-                        Err(_) => {
-                            sleep(Duration::from_secs(UPDATE_TIME)).await;
-                            break ;
-                        } 
+                match msg {
+                    Some(Ok(Message::Text(text))) => {
+                        if text.contains("\"type\":\"PING\"") {
+                            let pong = Message::Text("{\"type\":\"PONG\"}".into());
+                            write.send(pong).await.unwrap_or_else(|e| tracing::error!("Failed to send PONG to WebSocket: {e}"));
+                        }
+                        let campaigns = rx_campaigns.borrow().clone();
+                        handle_message(&client, &text, &campaigns).await;
+                    },
+                    Some(Ok(Message::Ping(ping))) => write.send(Message::Pong(ping)).await.unwrap_or_else(|e| tracing::error!("Failed to send PONG to WebSocket: {e}")),
+                    Some(Ok(_)) => {},
+                    Some(Err(_)) | None => {
+                        sleep(Duration::from_secs(UPDATE_TIME)).await;
+                        break;
                     }
                 }
             }
         }
-        
-        
     });
 }
 
 fn check_json<'a>(v: &'a Value, data: &str) -> Result<&'a Value, Box<dyn Error>> {
-    if let Some(key) = v.get(&data) {
-        return Ok(key);
+    if let Some(key) = v.get(data) {
+        Ok(key)
     } else {
         return Err(format!("Failed to find '{}' in JSON", data))?;
     }
 }
 
-#[derive(PartialEq, Eq, Clone)]
+#[derive(Clone)]
 struct Priority {
-    priority: u32,
-    name: Channel
+    score: f64,
+    name: Channel,
+    components: Vec<(&'static str, f64)>,
+    /// Selected campaigns the channel progresses and the games they belong to
+    campaigns: HashSet<String>,
+    games: HashSet<String>
+}
+
+impl From<Priority> for ScoredChannel {
+    fn from (priority: Priority) -> Self {
+        ScoredChannel { channel: priority.name, score: priority.score, components: priority.components }
+    }
 }
 
 impl Ord for Priority {
     fn cmp (&self, other: &Self) -> std::cmp::Ordering {
-        other.priority.cmp(&other.priority)
+        // Ties go to the lower channel id so the ranking is stable between rounds
+        self.score.total_cmp(&other.score).then_with(|| other.name.channel_id.cmp(&self.name.channel_id))
     }
 }
 
@@ -251,125 +439,304 @@ impl PartialOrd for Priority {
     }
 }
 
-async fn send_now_watched (mut rx: Receiver<BinaryHeap<Priority>>, tx_now_watch: broadcast::Sender<Channel>, notify: Arc<Notify>, tx_for_delete: tokio::sync::watch::Sender<Channel>) {
-    tokio::spawn(async move {
-        loop {
-            if let Ok(_) = rx.changed().await {
-                let watch = rx.borrow().clone();
-                    if let Some(max) = watch.peek() {
-                        debug!("Send: {}", max.name.channel_login);
-                        if let Err(e) = tx_now_watch.send(Channel { channel_id: max.name.channel_id.to_string(), channel_login: max.name.channel_login.to_string() }) {
-                            tracing::error!("{e}")
-                        };
-                        notify.notified().await;
-
-                        loop {
-                            if let Err(e) = tx_for_delete.send(max.name.clone()) {
-                                tracing::error!("{e}");
-                                sleep(Duration::from_secs(5)).await;
-                                continue;
-                            } else {
-                                break;
-                            }
-                        }
+impl PartialEq for Priority {
+    fn eq (&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
 
-                        sleep(Duration::from_secs(5)).await;
-                        continue;
-                    } else {
-                        sleep(Duration::from_secs(5)).await;
-                        continue;
-                    }  
-                
-            }
-            
+impl Eq for Priority {}
+
+/// What a watch slot is allowed to watch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlotTarget {
+    Any,
+    /// Channels progressing a campaign of this game id
+    Game(String),
+    /// Channels progressing this campaign id
+    Campaign(String)
+}
 
+impl SlotTarget {
+    fn matches (&self, priority: &Priority) -> bool {
+        match self {
+            SlotTarget::Any => true,
+            SlotTarget::Game(game_id) => priority.games.contains(game_id),
+            SlotTarget::Campaign(campaign_id) => priority.campaigns.contains(campaign_id),
         }
+    }
+}
 
-    });
+/// One concurrently watched channel: where to send it and how it reports that it has to switch.
+pub struct WatchSlot {
+    pub id: usize,
+    pub target: SlotTarget,
+    pub tx_now_watch: Sender<Channel>,
+    /// Ids of channels the slot has to leave (offline, no progress), reported by its watch and drop tasks
+    pub rx_leave: UnboundedReceiver<String>
 }
 
-pub async fn update_stream (campaigns: Arc<Vec<DropCampaigns>>, tx_now_watch: Sender<Channel>, notify: Arc<Notify>) {
-    tokio::spawn(async move {
-        let mut heap: BinaryHeap<Priority> = BinaryHeap::new();
-        let mut old_channel_ids: HashSet<Channel> = HashSet::new();
-        let (tx, rx) = tokio::sync::watch::channel(BinaryHeap::new());
-        let (tx_for_delete, mut rx_for_delete) = tokio::sync::watch::channel(Channel::default());
+/// Limits voluntary channel switches to avoid flapping between channels with similar scores.
+#[derive(Debug, Clone, Copy)]
+pub struct SwitchPolicy {
+    /// Minimum time on a channel before switching to a better one
+    pub min_dwell: Duration,
+    /// Score a competitor has to beat the watched channel by
+    pub margin: f64
+}
+
+impl SwitchPolicy {
+    /// Whether leaving `watched` after `dwelled` on it for `best` is worth it.
+    fn worth_switching (&self, watched: &Priority, best: &Priority, dwelled: Duration) -> bool {
+        dwelled >= self.min_dwell && best.name != watched.name && best.score > watched.score + self.margin
+    }
+}
+
+/// Channel ids from best to worst.
+fn ranking (heap: &BinaryHeap<Priority>) -> Vec<String> {
+    heap.clone().into_sorted_vec().into_iter().rev().map(|priority| priority.name.channel_id).collect()
+}
+
+/// Why the channel that slot `slot` is watching right now was picked.
+pub async fn current_selection (slot: usize) -> Option<Selection> {
+    SELECTION.lock().await.get(&slot).cloned()
+}
 
-        send_now_watched(rx, tx_now_watch, notify, tx_for_delete).await;
+/// Channels the slot may watch, best first: matching its target, not suspect, not watched by another slot
+/// and progressing a campaign no other slot progresses already, as a second channel does not speed a campaign up.
+fn candidates (slot: usize, target: &SlotTarget, heap: &BinaryHeap<Priority>, slot_channels: &HashMap<usize, Channel>, suspects: &HashSet<String>) -> Vec<Priority> {
+    let taken: HashSet<&String> = slot_channels.iter().filter(|(id, _)| **id != slot).map(|(_, channel)| &channel.channel_id).collect();
+    let covered: HashSet<&String> = heap.iter().filter(|priority| taken.contains(&priority.name.channel_id)).flat_map(|priority| &priority.campaigns).collect();
+    heap.clone().into_sorted_vec().into_iter().rev()
+        .filter(|priority| target.matches(priority) && !taken.contains(&priority.name.channel_id) && !suspects.contains(&priority.name.channel_id))
+        .filter(|priority| priority.campaigns.iter().any(|id| !covered.contains(id)))
+        .collect()
+}
+
+/// Claims the best channel for the slot that no other slot is watching and returns it with the runners-up behind it.
+async fn pick (slot: usize, target: &SlotTarget, heap: &BinaryHeap<Priority>) -> Option<(Channel, Selection)> {
+    let suspects = suspect_channels().await;
+    // Held until the pick is stored, so two slots can not claim the same channel
+    let mut slot_channels = SLOT_CHANNELS.lock().await;
+    let mut sorted = candidates(slot, target, heap, &slot_channels, &suspects).into_iter();
+    let Some(watched) = sorted.next() else {
+        slot_channels.remove(&slot);
+        return None;
+    };
+    let channel = watched.name.clone();
+    slot_channels.insert(slot, channel.clone());
+    Some((channel, Selection { watched: watched.into(), runners_up: sorted.take(RUNNERS_UP).map(ScoredChannel::from).collect() }))
+}
 
-        let channel_to_delete = Arc::new(Mutex::new(Channel::default()));
-        let channel_to_delete_clone = Arc::clone(&channel_to_delete);
-        tokio::spawn(async move {
+/// A better channel than the watched one, if switching to it is worth it: the slot stayed at least
+/// `min_dwell` and the competitor beats the watched channel by more than `margin`.
+async fn voluntary_switch (slot: usize, target: &SlotTarget, heap: &BinaryHeap<Priority>, watched: &Priority, since: DateTime<Utc>, policy: SwitchPolicy) -> Option<Priority> {
+    let dwelled = (Utc::now() - since).to_std().unwrap_or_default();
+    if dwelled < policy.min_dwell {
+        return None;
+    }
+    let suspects = suspect_channels().await;
+    let slot_channels = SLOT_CHANNELS.lock().await;
+    let best = candidates(slot, target, heap, &slot_channels, &suspects).into_iter().next()?;
+    policy.worth_switching(watched, &best, dwelled).then_some(best)
+}
+
+async fn send_now_watched (mut slot: WatchSlot, mut rx: Receiver<BinaryHeap<Priority>>, policy: SwitchPolicy) {
+    tokio::spawn(async move {
+        loop {
+            let watch = rx.borrow_and_update().clone();
+            // Reports that came in while nothing or another channel was watched are stale
+            while slot.rx_leave.try_recv().is_ok() {}
+            let Some((channel, selection)) = pick(slot.id, &slot.target, &watch).await else {
+                if rx.changed().await.is_err() {
+                    return;
+                }
+                continue;
+            };
+            debug!("Send: {}", channel.channel_login);
+            info!("Slot {} channel selection: {selection}", slot.id);
+            SELECTION.lock().await.insert(slot.id, selection);
+            if let Err(e) = slot.tx_now_watch.send(channel.clone()) {
+                tracing::error!("{e}")
+            };
+            let since = Utc::now();
+            // Re-checks the ranking once the dwell time is over, even if it did not change since
+            let dwell = sleep(policy.min_dwell);
+            tokio::pin!(dwell);
+            let mut dwelled = false;
             loop {
-                if let Ok(_) = rx_for_delete.changed().await {
-                    let channel = rx_for_delete.borrow().clone();
-                    let mut lock = channel_to_delete.lock().await;
-                    *lock = channel
+                tokio::select! {
+                    // Forced switches (offline, no progress, gone from the ranking) skip dwell time and margin
+                    Some(channel_id) = slot.rx_leave.recv() => {
+                        if channel_id != channel.channel_id {
+                            continue;
+                        }
+                        // Offline or not progressing: skip the channel for a while
+                        skip_channel(&channel.channel_id, SKIP_COOLDOWN).await;
+                        break;
+                    },
+                    _ = &mut dwell, if !dwelled => dwelled = true,
+                    changed = rx.changed() => {
+                        if changed.is_err() {
+                            return;
+                        }
+                    }
+                }
+                let heap = rx.borrow().clone();
+                let Some(watched) = heap.iter().find(|priority| priority.name == channel) else {
+                    debug!("{} is no longer a candidate", channel.channel_login);
+                    break;
+                };
+                if let Some(better) = voluntary_switch(slot.id, &slot.target, &heap, watched, since, policy).await {
+                    info!("Slot {}: switching from {} ({:.2}) to {} ({:.2})", slot.id, channel.channel_login, watched.score, better.name.channel_login, better.score);
+                    break;
                 }
             }
-        });
+            sleep(Duration::from_secs(5)).await;
+        }
+    });
+}
+
+/// Ranks the discovered channels every `UPDATE_TIME` seconds and lets every slot pick from the ranking.
+pub async fn update_stream (mut rx_campaigns: Receiver<Vec<DropCampaigns>>, slots: Vec<WatchSlot>, scorer: Arc<dyn ChannelScorer>, policy: SwitchPolicy) {
+    tokio::spawn(async move {
+        let (tx, rx) = tokio::sync::watch::channel(BinaryHeap::new());
+        for slot in slots {
+            send_now_watched(slot, rx.clone(), policy).await;
+        }
 
         loop {
+            let campaigns = rx_campaigns.borrow_and_update().clone();
             let channel_ids = CHANNEL_IDS.lock().await.clone();
             let allow_channels = ALLOW_CHANNELS.lock().await.clone();
             let default_channels = DEFAULT_CHANNELS.lock().await.clone();
-
-            if channel_ids.is_empty() || (allow_channels.is_empty() && default_channels.is_empty()) {
-                sleep(Duration::from_secs(UPDATE_TIME)).await;
-                continue;
+            let reputation = REPUTATION.lock().await.clone();
+            let stream_stats = STREAM_STATS.lock().await.clone();
+            let channel_campaigns = CHANNEL_CAMPAIGNS.lock().await.clone();
+            let incomplete = INCOMPLETE_CAMPAIGNS.lock().await.clone();
+            let has_drops_left = |id: &String| incomplete.as_ref().is_none_or(|incomplete| incomplete.contains(id));
+
+            // Suspect channels stay out of the ranking until their cooldown ends
+            let suspects = suspect_channels().await;
+
+            // Campaigns with drops left, the one that ends first leads
+            let mut by_deadline: Vec<&DropCampaigns> = campaigns.iter().filter(|campaign| has_drops_left(&campaign.id)).collect();
+            by_deadline.sort_by_key(|campaign| parse_time(&campaign.endAt).unwrap_or(DateTime::<Utc>::MAX_UTC));
+
+            // Every channel is scored once, no matter how many campaigns list it
+            let mut heap = BinaryHeap::new();
+            for channel in channel_ids.iter().filter(|channel| !suspects.contains(&channel.channel_id)) {
+                let allowlisted: HashSet<&String> = campaigns.iter().filter(|campaign| allow_channels.get(&campaign.id).is_some_and(|allow| allow.iter().any(|c| c.id == channel.channel_id))).map(|campaign| &campaign.id).collect();
+                let listed: HashSet<&String> = campaigns.iter().filter(|campaign| default_channels.get(&campaign.id).is_some_and(|default| default.iter().any(|c| c.broadcaster.id == channel.channel_id))).map(|campaign| &campaign.id).collect();
+                // Prefer what the channel actually progresses over where it was listed,
+                // and only count campaigns that still have drops left
+                let covered: HashSet<String> = match channel_campaigns.get(&channel.channel_id) {
+                    Some(covered) => covered.iter().filter(|id| has_drops_left(id)).cloned().collect(),
+                    None => allowlisted.union(&listed).filter(|id| has_drops_left(id)).map(|id| id.to_string()).collect(),
+                };
+                if covered.is_empty() {
+                    continue;
+                }
+                let stats = stream_stats.get(&channel.channel_id);
+                let candidate = Candidate {
+                    channel: channel.clone(),
+                    source: if allowlisted.is_empty() { ChannelSource::Directory } else { ChannelSource::Allowlist },
+                    campaigns: covered.len(),
+                    deadline_rank: by_deadline.iter().position(|campaign| covered.contains(&campaign.id)),
+                    viewers: stats.map(|stats| stats.viewers),
+                    tags: stats.map(|stats| stats.tags.clone()).unwrap_or_default(),
+                    live_since: stats.map(|stats| stats.live_since),
+                    reputation: reputation.get(&channel.channel_id).map_or(NEUTRAL_SCORE, |stats| stats.credit_score()),
+                    penalty: reputation.get(&channel.channel_id).map_or(0, |stats| stats.penalty()),
+                };
+                let scored = scorer.score(&candidate);
+                let games = campaigns.iter().filter(|campaign| covered.contains(&campaign.id)).map(|campaign| campaign.game.id.clone()).collect();
+                heap.push(Priority { score: scored.score, name: scored.channel, components: scored.components, campaigns: covered, games });
             }
 
-            let added_channels: Vec<&Channel> = channel_ids.iter().filter(|id| !old_channel_ids.contains(id)).collect();
-            let mut delete_channels: Vec<&Channel> = old_channel_ids.iter().filter(|id| !channel_ids.contains(id)).collect();
-
-            let add_channel_to_delete = channel_to_delete_clone.lock().await;
-            if *add_channel_to_delete != Channel::default() {
-                delete_channels.push(&add_channel_to_delete);
+            if !heap.is_empty() {
+                *LAST_CANDIDATE.lock().await = Some(Utc::now());
             }
-
-            if !delete_channels.is_empty() {
-                let delete_set: HashSet<&Channel> = delete_channels.into_iter().collect();
-                let mut new_heap = BinaryHeap::new();
-                while let Some(item) = heap.pop() {
-                    if !delete_set.iter().any(|channel| channel.channel_id == item.name.channel_id) {
-                        new_heap.push(item);
-                    }
+            tx.send_if_modified(|current| {
+                let modified = ranking(current) != ranking(&heap);
+                if modified {
+                    *current = heap;
                 }
-                heap = new_heap
+                modified
+            });
+            tokio::select! {
+                _ = sleep(Duration::from_secs(UPDATE_TIME)) => {},
+                _ = rx_campaigns.changed() => {},
+                // A preferred channel went live or a channel stopped earning drops
+                _ = RERANK.notified() => {}
             }
-            if !added_channels.is_empty() {
-                for drop_id in campaigns.iter() {
-                    for channel in &channel_ids {
-                        debug!("{}", channel.channel_id);
-                        debug!("{}", drop_id.id);
-                        if let Some(allow) = allow_channels.get(&drop_id.id) {
-                                if let Some(channel_allow) = allow.iter().find(|s| s.id == *channel.channel_id) {
-                                    let channel = Channel { channel_id: channel_allow.id.clone(), channel_login: channel_allow.name.clone() };
-                                    if !channel_ids.contains(&channel) {
-                                        heap.push(Priority { priority: 1, name: channel.clone() });
-                                    }
-                                    debug!("Allow {}", channel_allow.name);
-                                    heap.push(Priority { priority: 3, name: channel });
-                                }
-                        }
+        }
+    });
+}
 
-                        if let Some(default) = default_channels.get(&drop_id.id) {
-                            if let Some(channel_default) = default.iter().find(|s| s.broadcaster.id == *channel.channel_id) {
-                                debug!("Default {}", channel_default.broadcaster.login);
-                                let channel = Channel { channel_id: channel_default.broadcaster.id.clone(), channel_login: channel_default.broadcaster.login.clone() };
-                                if !channel_ids.contains(&channel) {
-                                    heap.push(Priority { priority: 0, name: channel.clone() });
-                                }
-                                heap.push(Priority { priority: 2, name: channel });
-                            }
-                        }
+#[cfg(test)]
+mod tests {
+    use std::{collections::{BinaryHeap, HashMap, HashSet}, time::Duration};
 
-                    } 
-                }
-                old_channel_ids = channel_ids
-            }
-            tx.send(heap.clone()).unwrap();
-            sleep(Duration::from_secs(UPDATE_TIME)).await;
+    use crate::r#static::Channel;
+
+    use super::{Priority, SlotTarget, SwitchPolicy, candidates};
+
+    fn channel (channel_id: &str) -> Channel {
+        Channel { channel_id: channel_id.to_string(), channel_login: channel_id.to_string() }
+    }
+
+    fn priority (channel_id: &str, score: f64, campaigns: &[&str], game: &str) -> Priority {
+        Priority {
+            score,
+            name: channel(channel_id),
+            components: Vec::new(),
+            campaigns: campaigns.iter().map(|id| id.to_string()).collect(),
+            games: HashSet::from([game.to_string()])
         }
-    });
-}
\ No newline at end of file
+    }
+
+    fn ids (priorities: Vec<Priority>) -> Vec<String> {
+        priorities.into_iter().map(|priority| priority.name.channel_id).collect()
+    }
+
+    #[test]
+    fn slot_target_matches_game_or_campaign () {
+        let priority = priority("a", 1.0, &["c1"], "g1");
+        assert!(SlotTarget::Any.matches(&priority));
+        assert!(SlotTarget::Game("g1".to_string()).matches(&priority));
+        assert!(!SlotTarget::Game("g2".to_string()).matches(&priority));
+        assert!(SlotTarget::Campaign("c1".to_string()).matches(&priority));
+        assert!(!SlotTarget::Campaign("c2".to_string()).matches(&priority));
+    }
+
+    #[test]
+    fn candidates_are_best_first_and_skip_suspects () {
+        let heap = BinaryHeap::from([priority("a", 1.0, &["c1"], "g1"), priority("b", 3.0, &["c2"], "g1"), priority("c", 2.0, &["c3"], "g2")]);
+        assert_eq!(ids(candidates(0, &SlotTarget::Any, &heap, &HashMap::new(), &HashSet::new())), ["b", "c", "a"]);
+        assert_eq!(ids(candidates(0, &SlotTarget::Game("g2".to_string()), &heap, &HashMap::new(), &HashSet::new())), ["c"]);
+        assert_eq!(ids(candidates(0, &SlotTarget::Any, &heap, &HashMap::new(), &HashSet::from(["b".to_string()]))), ["c", "a"]);
+    }
+
+    #[test]
+    fn candidates_leave_out_what_other_slots_cover () {
+        let heap = BinaryHeap::from([priority("a", 3.0, &["c1"], "g1"), priority("b", 2.0, &["c1"], "g1"), priority("c", 1.0, &["c1", "c2"], "g1")]);
+        // Slot 1 watches "a", so slot 0 only gets channels that progress another campaign
+        let slot_channels = HashMap::from([(1, channel("a"))]);
+        assert_eq!(ids(candidates(0, &SlotTarget::Any, &heap, &slot_channels, &HashSet::new())), ["c"]);
+        // The slot's own channel does not count as taken
+        assert_eq!(ids(candidates(1, &SlotTarget::Any, &heap, &slot_channels, &HashSet::new())), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn switch_needs_dwell_time_and_margin () {
+        let policy = SwitchPolicy { min_dwell: Duration::from_secs(600), margin: 0.5 };
+        let watched = priority("a", 1.0, &["c1"], "g1");
+        let dwelled = Duration::from_secs(600);
+        assert!(policy.worth_switching(&watched, &priority("b", 1.6, &["c1"], "g1"), dwelled));
+        // Too early, within the margin, or the same channel
+        assert!(!policy.worth_switching(&watched, &priority("b", 1.6, &["c1"], "g1"), Duration::from_secs(599)));
+        assert!(!policy.worth_switching(&watched, &priority("b", 1.5, &["c1"], "g1"), dwelled));
+        assert!(!policy.worth_switching(&watched, &priority("a", 9.0, &["c1"], "g1"), dwelled));
+    }
+}